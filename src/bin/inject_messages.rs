@@ -13,7 +13,9 @@ use tracing_subscriber::FmtSubscriber;
 
 
 
-use account_demo::db::{MessageStore, Db};
+use account_demo::db::{Db, MessageStore, PostgresMessageStore};
+use account_demo::domain::account::Account;
+use account_demo::messaging::Metadata;
 
 #[tokio::main]
 async fn main() {
@@ -30,19 +32,30 @@ async fn main() {
     let db = Db::new(&database_url).await
         .expect("Failed to create database connection pool");
 
-    let store = MessageStore::new(db);
+    let store = PostgresMessageStore::new(db);
 
+    let account_id = uuid::Uuid::new_v4().to_string();
     let data = serde_json::json!({
-        "account_id": uuid::Uuid::new_v4().to_string(),
+        "account_id": account_id,
         "name": "Alice",
     }).to_string();
 
-    store.write_message(
-        "account:commands",
+    // Set CORRELATION_STREAM_NAME to have the resulting events delivered to a correlated subscriber
+    let metadata = Metadata {
+        correlation_stream_name: env::var("CORRELATION_STREAM_NAME").ok(),
+        trace_id: Some(uuid::Uuid::new_v4().to_string()),
+        ..Metadata::default()
+    };
+
+    match store.write_message(
+        Account::commands_stream_name(&account_id).as_str(),
         "Open",
         &data,
-        None,
-        None).await;
+        Some(&metadata),
+        None).await {
+        Ok(position) => println!("Wrote Open command at position {}", position),
+        Err(e) => eprintln!("Failed to write Open command: {}", e),
+    }
 }
 
 
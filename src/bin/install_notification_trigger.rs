@@ -0,0 +1,32 @@
+use dotenv::dotenv;
+use std::env;
+use std::process;
+
+use tracing::{error, Level};
+use tracing_subscriber::FmtSubscriber;
+
+use account_demo::db::{Db, PostgresMessageStore};
+
+/// Installs the trigger that wakes subscriptions through LISTEN/NOTIFY. Run once
+/// per database, with a role that may alter `message_store.messages`; consumers
+/// only check that it is there and poll without it.
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::INFO)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("setting default subscriber failed");
+
+    let database_url = env::var("DATABASE_URL")
+        .expect("DATABASE_URL must be set in .env file");
+
+    let db = Db::new(&database_url).await
+        .expect("Failed to create database connection pool");
+
+    if let Err(e) = PostgresMessageStore::new(db).install_notification_trigger().await {
+        error!("Failed to install notification trigger: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,234 @@
+use dotenv::dotenv;
+use std::env;
+use std::process;
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use futures::TryStreamExt;
+use tracing::Level;
+use tracing_subscriber::FmtSubscriber;
+
+use account_demo::db::{Db, MessageStore, PostgresMessageStore};
+use account_demo::domain::account::Account;
+use account_demo::messaging::{stream_name, ErrorPolicy, Message, Metadata};
+
+const USAGE: &str = "\
+Usage:
+  replay_dead_letters list [filters]
+  replay_dead_letters replay [filters] [--position <global position>]...
+
+Filters:
+  --stream <name>     dead-letter stream (default: account:commands+dlq)
+  --type <type>       only messages of this type, e.g. Deposit
+  --account <id>      only messages for this account id
+  --since <time>      only messages dead-lettered at or after this time
+  --until <time>      only messages dead-lettered before this time
+
+Times are YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS, in UTC. Replay writes each
+selected message back to its account's command stream (account:commands-<id>)
+with causation metadata pointing at the dead-letter entry. Entries that were
+already replayed are skipped.";
+
+const BATCH_SIZE: i64 = 1000;
+
+#[derive(Default)]
+struct Options {
+    stream: Option<String>,
+    message_type: Option<String>,
+    account_id: Option<String>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    positions: Vec<i64>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+            match arg.as_str() {
+                "--stream" => options.stream = Some(value()?.clone()),
+                "--type" => options.message_type = Some(value()?.clone()),
+                "--account" => options.account_id = Some(value()?.clone()),
+                "--since" => options.since = Some(parse_time(value()?)?),
+                "--until" => options.until = Some(parse_time(value()?)?),
+                "--position" => {
+                    let position = value()?;
+                    options.positions.push(position.parse().map_err(|_| format!("Invalid position: {}", position))?);
+                },
+                other => return Err(format!("Unknown option: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+
+    fn matches(&self, message: &Message) -> bool {
+        if let Some(ref message_type) = self.message_type {
+            if &message.message_type != message_type {
+                return false;
+            }
+        }
+        if let Some(ref account_id) = self.account_id {
+            if account_id_of(message).as_deref() != Some(account_id.as_str()) {
+                return false;
+            }
+        }
+        if matches!(self.since, Some(since) if message.time < since) {
+            return false;
+        }
+        if matches!(self.until, Some(until) if message.time >= until) {
+            return false;
+        }
+        if !self.positions.is_empty() && !message.global_position.is_some_and(|p| self.positions.contains(&p)) {
+            return false;
+        }
+        true
+    }
+}
+
+fn parse_time(value: &str) -> Result<NaiveDateTime, String> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(time);
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).expect("midnight is a valid time"))
+        .map_err(|_| format!("Invalid time: {}", value))
+}
+
+fn account_id_of(message: &Message) -> Option<String> {
+    let data: serde_json::Value = serde_json::from_str(&message.data).ok()?;
+    data["account_id"].as_str().map(|s| s.to_string())
+}
+
+fn property(metadata: &Metadata, name: &str) -> String {
+    match metadata.properties.get(name) {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+async fn dead_letters(store: &Arc<dyn MessageStore>, stream: &str, options: &Options) -> Result<Vec<Message>, String> {
+    // The default dead-letter stream, account:commands+dlq, has no id, so Message DB
+    // only reads it as a category
+    let messages = if stream_name::is_category(stream) {
+        store.read_category(stream, None, BATCH_SIZE)
+    } else {
+        store.read_stream(stream, None, BATCH_SIZE)
+    };
+    messages
+        .try_filter(|message| futures::future::ready(options.matches(message)))
+        .try_collect()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", stream, e))
+}
+
+async fn list(store: &Arc<dyn MessageStore>, stream: &str, options: &Options) -> Result<(), String> {
+    let messages = dead_letters(store, stream, options).await?;
+    for message in &messages {
+        println!(
+            "{}\t{}\t{}\t{}\taccount={}\tattempts={}\treason={}",
+            message.global_position.unwrap_or_default(),
+            message.time,
+            message.message_type,
+            message.id,
+            account_id_of(message).unwrap_or_else(|| "-".to_string()),
+            property(&message.metadata, "failedAttempts"),
+            property(&message.metadata, "failureReason"),
+        );
+    }
+    println!("{} dead-lettered messages in {}", messages.len(), stream);
+    Ok(())
+}
+
+/// Whether a command caused by this dead-letter entry was already written, i.e. it
+/// was replayed before. Replaying it again would apply it twice, since the replayed
+/// command has a new global position.
+async fn replayed(store: &Arc<dyn MessageStore>, commands_stream: &str, message: &Message) -> Result<bool, String> {
+    let replay = store.read_stream(commands_stream, None, BATCH_SIZE)
+        .try_filter(|command| futures::future::ready(
+            command.metadata.causation_message_global_position == message.global_position
+                && command.metadata.causation_message_stream_name.as_deref() == Some(message.stream_name.as_str())
+        ))
+        .try_next()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", commands_stream, e))?;
+    Ok(replay.is_some())
+}
+
+async fn replay(store: &Arc<dyn MessageStore>, stream: &str, options: &Options) -> Result<(), String> {
+    let messages = dead_letters(store, stream, options).await?;
+    let mut replayed_count = 0;
+    for message in &messages {
+        let account_id = account_id_of(message)
+            .ok_or_else(|| format!("Cannot replay message {}: no account_id in its data", message.id))?;
+        let commands_stream = Account::commands_stream_name(&account_id);
+        if replayed(store, commands_stream.as_str(), message).await? {
+            println!("Skipping {} {}: already replayed to {}", message.message_type, message.id, commands_stream);
+            continue;
+        }
+
+        // Causation points at the dead-letter entry, which in turn points at the original
+        let metadata = Metadata::follow(message);
+        let position = store.write_message(
+            commands_stream.as_str(),
+            &message.message_type,
+            &message.data,
+            Some(&metadata),
+            None,
+        ).await
+            .map_err(|e| format!("Failed to replay message {}: {}", message.id, e))?;
+
+        println!("Replayed {} {} to {} at position {}", message.message_type, message.id, commands_stream, position);
+        replayed_count += 1;
+    }
+    println!("{} messages replayed from {}", replayed_count, stream);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::WARN)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("setting default subscriber failed");
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (command, options) = match args.split_first() {
+        Some((command, rest)) => match Options::parse(rest) {
+            Ok(options) => (command.clone(), options),
+            Err(e) => {
+                eprintln!("{}\n\n{}", e, USAGE);
+                process::exit(2);
+            }
+        },
+        None => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let database_url = env::var("DATABASE_URL")
+        .expect("DATABASE_URL must be set in .env file");
+
+    let db = Db::new(&database_url).await
+        .expect("Failed to create database connection pool");
+
+    let store: Arc<dyn MessageStore> = Arc::new(PostgresMessageStore::new(db));
+    let stream = options.stream.clone()
+        .unwrap_or_else(|| ErrorPolicy::default().dead_letter_stream_name(Account::commands_category().as_str()));
+
+    let result = match command.as_str() {
+        "list" => list(&store, &stream, &options).await,
+        "replay" => replay(&store, &stream, &options).await,
+        other => Err(format!("Unknown command: {}\n\n{}", other, USAGE)),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
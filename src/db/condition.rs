@@ -0,0 +1,72 @@
+use std::fmt;
+
+/// SQL condition passed through to Message DB's `get_stream_messages` and
+/// `get_category_messages`. The server only accepts conditions when
+/// `message_store.sql_condition` is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// SQL used as-is. The caller is responsible for quoting.
+    Raw(String),
+    /// Matches no message when empty.
+    MessageTypeIn(Vec<String>),
+    MetadataEq(String, String),
+    DataEq(String, String),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    pub fn raw(sql: &str) -> Self {
+        Condition::Raw(sql.to_string())
+    }
+
+    pub fn message_type(message_type: &str) -> Self {
+        Condition::MessageTypeIn(vec![message_type.to_string()])
+    }
+
+    pub fn message_type_in(message_types: &[&str]) -> Self {
+        Condition::MessageTypeIn(message_types.iter().map(|t| t.to_string()).collect())
+    }
+
+    pub fn metadata_eq(field: &str, value: &str) -> Self {
+        Condition::MetadataEq(field.to_string(), value.to_string())
+    }
+
+    pub fn data_eq(field: &str, value: &str) -> Self {
+        Condition::DataEq(field.to_string(), value.to_string())
+    }
+
+    pub fn and(self, other: Condition) -> Self {
+        Condition::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Condition) -> Self {
+        Condition::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn to_sql(&self) -> String {
+        match self {
+            Condition::Raw(sql) => sql.clone(),
+            // `type IN ()` is a syntax error in Postgres
+            Condition::MessageTypeIn(types) if types.is_empty() => "FALSE".to_string(),
+            Condition::MessageTypeIn(types) => {
+                let types: Vec<String> = types.iter().map(|t| quote(t)).collect();
+                format!("type IN ({})", types.join(", "))
+            },
+            Condition::MetadataEq(field, value) => format!("metadata->>{} = {}", quote(field), quote(value)),
+            Condition::DataEq(field, value) => format!("data->>{} = {}", quote(field), quote(value)),
+            Condition::And(left, right) => format!("({}) AND ({})", left.to_sql(), right.to_sql()),
+            Condition::Or(left, right) => format!("({}) OR ({})", left.to_sql(), right.to_sql()),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_sql())
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
@@ -0,0 +1,125 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum MessageStoreError {
+    Connection(sqlx::Error),
+    Database(sqlx::Error),
+    Serialization(String),
+    ExpectedVersion {
+        stream_name: String,
+        expected: i64,
+        actual: i64,
+    },
+    NotFound(String),
+    ConditionNotActivated,
+    /// A category was read as a stream, or a stream as a category. Message DB
+    /// tells them apart by whether the name has an id after a `-`.
+    InvalidStreamName(String),
+    Unsupported(String),
+}
+
+impl MessageStoreError {
+    pub fn is_expected_version(&self) -> bool {
+        matches!(self, MessageStoreError::ExpectedVersion { .. })
+    }
+
+    /// Whether retrying the same operation later might succeed, e.g. during a failover.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MessageStoreError::Connection(_) => true,
+            MessageStoreError::Database(sqlx::Error::Database(db_error)) => {
+                // Connection exceptions, serialization failures and deadlocks,
+                // insufficient resources, and server shutdown or startup
+                db_error.code().is_some_and(|code| {
+                    code.starts_with("08")
+                        || code.starts_with("40")
+                        || code.starts_with("53")
+                        || code == "57P01"
+                        || code == "57P02"
+                        || code == "57P03"
+                })
+            },
+            _ => false,
+        }
+    }
+
+    // Message DB raises:
+    //   Wrong expected version: <expected> (Stream: <stream_name>, Stream Version: <actual>)
+    fn parse_expected_version(message: &str) -> Option<MessageStoreError> {
+        let rest = message.strip_prefix("Wrong expected version: ")?;
+        let (expected, rest) = rest.split_once(" (Stream: ")?;
+        let (stream_name, rest) = rest.rsplit_once(", Stream Version: ")?;
+        let actual = rest.strip_suffix(')')?;
+
+        Some(MessageStoreError::ExpectedVersion {
+            stream_name: stream_name.to_string(),
+            expected: expected.trim().parse().ok()?,
+            actual: actual.trim().parse().ok()?,
+        })
+    }
+}
+
+impl fmt::Display for MessageStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageStoreError::Connection(e) => write!(f, "Connection error: {}", e),
+            MessageStoreError::Database(e) => write!(f, "Database error: {}", e),
+            MessageStoreError::Serialization(e) => write!(f, "Serialization error: {}", e),
+            MessageStoreError::ExpectedVersion { stream_name, expected, actual } => write!(
+                f,
+                "Wrong expected version: {} (Stream: {}, Stream Version: {})",
+                expected, stream_name, actual
+            ),
+            MessageStoreError::NotFound(e) => write!(f, "Not found: {}", e),
+            MessageStoreError::ConditionNotActivated => write!(
+                f,
+                "Retrieval with SQL condition is not activated (set message_store.sql_condition to on)"
+            ),
+            MessageStoreError::InvalidStreamName(e) => write!(f, "{}", e),
+            MessageStoreError::Unsupported(e) => write!(f, "Unsupported: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MessageStoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MessageStoreError::Connection(e) | MessageStoreError::Database(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for MessageStoreError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::Database(ref db_error) => {
+                let message = db_error.message();
+                if message.starts_with("Retrieval with SQL condition is not activated") {
+                    return MessageStoreError::ConditionNotActivated;
+                }
+                if message.starts_with("Must be a stream name") || message.starts_with("Must be a category") {
+                    return MessageStoreError::InvalidStreamName(message.to_string());
+                }
+                MessageStoreError::parse_expected_version(message)
+                    .unwrap_or(MessageStoreError::Database(e))
+            },
+            sqlx::Error::RowNotFound => MessageStoreError::NotFound(e.to_string()),
+            sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed => MessageStoreError::Connection(e),
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => {
+                MessageStoreError::Serialization(e.to_string())
+            },
+            _ => MessageStoreError::Database(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for MessageStoreError {
+    fn from(e: serde_json::Error) -> Self {
+        MessageStoreError::Serialization(e.to_string())
+    }
+}
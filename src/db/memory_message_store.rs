@@ -0,0 +1,455 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::async_trait;
+use md5::{Digest, Md5};
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::{debug, info};
+
+use crate::db::{Condition, MessageStore, MessageStoreError, NewMessage, Notifications};
+use crate::messaging::message::Message;
+use crate::messaging::stream_name;
+use crate::messaging::Metadata;
+
+/// Message store held in memory, for running handlers and consumers without Postgres.
+///
+/// Follows the same rules as Message DB: expected versions are checked per stream,
+/// global positions start at 1, and category reads honour correlation and consumer
+/// groups using Message DB's own hashing of the cardinal id.
+#[derive(Debug, Clone)]
+pub struct InMemoryMessageStore {
+    messages: Arc<Mutex<Vec<Message>>>,
+    notifications: broadcast::Sender<String>,
+}
+
+impl Default for InMemoryMessageStore {
+    fn default() -> Self {
+        InMemoryMessageStore::new()
+    }
+}
+
+impl InMemoryMessageStore {
+    pub fn new() -> Self {
+        let (notifications, _) = broadcast::channel(1024);
+        InMemoryMessageStore {
+            messages: Arc::new(Mutex::new(Vec::new())),
+            notifications,
+        }
+    }
+
+    /// Every message written so far, in global position order.
+    pub fn messages(&self) -> Vec<Message> {
+        self.messages.lock().expect("message store lock poisoned").clone()
+    }
+
+    fn notify(&self, stream_names: &[&str]) {
+        for name in stream_names {
+            // No receivers just means nobody is subscribed
+            let _ = self.notifications.send(stream_name::category(name).to_string());
+        }
+    }
+}
+
+fn stream_version(messages: &[Message], stream_name: &str) -> Option<i64> {
+    messages.iter()
+        .filter(|message| message.stream_name == stream_name)
+        .filter_map(|message| message.position)
+        .max()
+}
+
+fn append(
+    messages: &mut Vec<Message>,
+    new_message: &NewMessage,
+    expected_version: Option<i64>
+) -> Result<i64, MessageStoreError> {
+    let version = stream_version(messages, &new_message.stream_name);
+
+    if let Some(expected) = expected_version {
+        let actual = version.unwrap_or(-1);
+        if expected != actual {
+            return Err(MessageStoreError::ExpectedVersion {
+                stream_name: new_message.stream_name.clone(),
+                expected,
+                actual,
+            });
+        }
+    }
+
+    let position = version.map_or(0, |version| version + 1);
+    let global_position = messages.last()
+        .and_then(|message| message.global_position)
+        .map_or(1, |global_position| global_position + 1);
+
+    messages.push(Message {
+        id: uuid::Uuid::new_v4().to_string(),
+        stream_name: new_message.stream_name.clone(),
+        global_position: Some(global_position),
+        position: Some(position),
+        message_type: new_message.message_type.clone(),
+        data: new_message.data.clone(),
+        metadata: new_message.metadata.clone().unwrap_or_default(),
+        time: chrono::Utc::now().naive_utc(),
+    });
+
+    Ok(position)
+}
+
+// Message DB's hash_64: the first 64 bits of the md5 of the value, as a signed bigint
+fn hash_64(value: &str) -> i64 {
+    let digest = Md5::digest(value.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    i64::from_be_bytes(bytes)
+}
+
+// Like Message DB, a stream without an id has a NULL cardinal id and belongs to no member
+fn in_consumer_group(message: &Message, member: i64, size: i64) -> bool {
+    stream_name::cardinal_id(&message.stream_name)
+        .is_some_and(|cardinal_id| (hash_64(cardinal_id).unsigned_abs() % size as u64) as i64 == member)
+}
+
+fn json_field_text(json: &Value, field: &str) -> Option<String> {
+    match &json[field] {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+fn matches_condition(message: &Message, condition: &Condition) -> Result<bool, MessageStoreError> {
+    match condition {
+        Condition::Raw(sql) => Err(MessageStoreError::Unsupported(
+            format!("Raw SQL condition in the in-memory message store: {}", sql)
+        )),
+        Condition::MessageTypeIn(types) => Ok(types.contains(&message.message_type)),
+        Condition::MetadataEq(field, value) => {
+            let metadata = serde_json::to_value(&message.metadata)?;
+            Ok(json_field_text(&metadata, field).as_deref() == Some(value.as_str()))
+        },
+        Condition::DataEq(field, value) => {
+            let data: Value = serde_json::from_str(&message.data)?;
+            Ok(json_field_text(&data, field).as_deref() == Some(value.as_str()))
+        },
+        Condition::And(left, right) => Ok(matches_condition(message, left)? && matches_condition(message, right)?),
+        Condition::Or(left, right) => Ok(matches_condition(message, left)? || matches_condition(message, right)?),
+    }
+}
+
+fn filter_condition(messages: Vec<Message>, condition: Option<&Condition>) -> Result<Vec<Message>, MessageStoreError> {
+    let condition = match condition {
+        Some(condition) => condition,
+        None => return Ok(messages),
+    };
+
+    let mut matching = Vec::new();
+    for message in messages {
+        if matches_condition(&message, condition)? {
+            matching.push(message);
+        }
+    }
+    Ok(matching)
+}
+
+#[async_trait]
+impl MessageStore for InMemoryMessageStore {
+    async fn write_message(
+        &self,
+        stream_name: &str,
+        message_type: &str,
+        data: &str,
+        metadata: Option<&Metadata>,
+        expected_version: Option<i64>
+    ) -> Result<i64, MessageStoreError> {
+        let new_message = NewMessage {
+            metadata: metadata.cloned(),
+            ..NewMessage::new(stream_name, message_type, data)
+        };
+
+        let position = {
+            let mut messages = self.messages.lock().expect("message store lock poisoned");
+            append(&mut messages, &new_message, expected_version)?
+        };
+
+        debug!("Message written to {} at position {}", stream_name, position);
+        self.notify(&[stream_name]);
+        Ok(position)
+    }
+
+    async fn write_batch(
+        &self,
+        messages: &[NewMessage],
+        expected_version: Option<i64>
+    ) -> Result<Vec<i64>, MessageStoreError> {
+        let positions = {
+            let mut store = self.messages.lock().expect("message store lock poisoned");
+
+            // Stage the batch on a copy so a conflict leaves the store untouched
+            let mut staged = store.clone();
+            let mut positions = Vec::with_capacity(messages.len());
+            for (index, message) in messages.iter().enumerate() {
+                let written_before = messages[..index].iter()
+                    .any(|previous| previous.stream_name == message.stream_name);
+                let expected = if written_before {
+                    stream_version(&staged, &message.stream_name)
+                } else if index == 0 {
+                    expected_version
+                } else {
+                    None
+                };
+                positions.push(append(&mut staged, message, expected)?);
+            }
+
+            *store = staged;
+            positions
+        };
+
+        info!("Batch of {} messages written successfully", positions.len());
+        let stream_names: Vec<&str> = messages.iter().map(|message| message.stream_name.as_str()).collect();
+        self.notify(&stream_names);
+        Ok(positions)
+    }
+
+    async fn get_stream_messages(
+        &self,
+        stream_name: &str,
+        position: Option<i64>,
+        batch_size: Option<i64>,
+        condition: Option<&Condition>
+    ) -> Result<Vec<Message>, MessageStoreError> {
+        if stream_name::is_category(stream_name) {
+            return Err(MessageStoreError::InvalidStreamName(format!("Must be a stream name: {}", stream_name)));
+        }
+
+        let position = position.unwrap_or(0);
+        let messages: Vec<Message> = self.messages.lock().expect("message store lock poisoned")
+            .iter()
+            .filter(|message| message.stream_name == stream_name)
+            .filter(|message| message.position.unwrap_or(0) >= position)
+            .cloned()
+            .collect();
+
+        let mut messages = filter_condition(messages, condition)?;
+        messages.truncate(batch_size.unwrap_or(1000).max(0) as usize);
+        Ok(messages)
+    }
+
+    async fn get_category_messages(
+        &self,
+        category_name: &str,
+        position: Option<i64>,
+        batch_size: Option<i64>,
+        correlation: Option<&str>,
+        consumer_group_member: Option<i64>,
+        consumer_group_size: Option<i64>,
+        condition: Option<&Condition>
+    ) -> Result<Vec<Message>, MessageStoreError> {
+        if !stream_name::is_category(category_name) {
+            return Err(MessageStoreError::InvalidStreamName(format!("Must be a category: {}", category_name)));
+        }
+
+        let position = position.unwrap_or(1);
+        let consumer_group = match (consumer_group_member, consumer_group_size) {
+            (Some(member), Some(size)) if size > 0 => Some((member, size)),
+            _ => None,
+        };
+
+        let messages: Vec<Message> = self.messages.lock().expect("message store lock poisoned")
+            .iter()
+            .filter(|message| stream_name::category(&message.stream_name) == category_name)
+            .filter(|message| message.global_position.unwrap_or(0) >= position)
+            .filter(|message| match correlation {
+                Some(correlation) => message.metadata.correlation_stream_name.as_deref()
+                    .map(stream_name::category) == Some(correlation),
+                None => true,
+            })
+            .filter(|message| match consumer_group {
+                Some((member, size)) => in_consumer_group(message, member, size),
+                None => true,
+            })
+            .cloned()
+            .collect();
+
+        let mut messages = filter_condition(messages, condition)?;
+        messages.truncate(batch_size.unwrap_or(1000).max(0) as usize);
+        Ok(messages)
+    }
+
+    async fn get_last_message(
+        &self,
+        stream_name: &str
+    ) -> Result<Option<Message>, MessageStoreError> {
+        let messages = self.messages.lock().expect("message store lock poisoned");
+        Ok(messages.iter().rev().find(|message| message.stream_name == stream_name).cloned())
+    }
+
+    async fn stream_version(
+        &self,
+        stream_name: &str
+    ) -> Result<Option<i64>, MessageStoreError> {
+        let messages = self.messages.lock().expect("message store lock poisoned");
+        Ok(stream_version(&messages, stream_name))
+    }
+
+    async fn notifications(
+        &self,
+        category: &str
+    ) -> Result<Option<Box<dyn Notifications>>, MessageStoreError> {
+        Ok(Some(Box::new(InMemoryNotifications {
+            receiver: self.notifications.subscribe(),
+            category: category.to_string(),
+        })))
+    }
+}
+
+struct InMemoryNotifications {
+    receiver: broadcast::Receiver<String>,
+    category: String,
+}
+
+#[async_trait]
+impl Notifications for InMemoryNotifications {
+    async fn wait(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match tokio::time::timeout_at(deadline, self.receiver.recv()).await {
+                Ok(Ok(category)) if category == self.category => return,
+                Ok(Ok(_)) => continue,
+                // Missed notifications may have been for this category, so read again
+                Ok(Err(broadcast::error::RecvError::Lagged(_))) => return,
+                Ok(Err(broadcast::error::RecvError::Closed)) => {
+                    tokio::time::sleep_until(deadline).await;
+                    return;
+                },
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn write(store: &InMemoryMessageStore, stream_name: &str) -> i64 {
+        store.write_message(stream_name, "Opened", "{}", None, None).await.unwrap()
+    }
+
+    fn global_positions(messages: &[Message]) -> Vec<i64> {
+        messages.iter().filter_map(|message| message.global_position).collect()
+    }
+
+    #[tokio::test]
+    async fn expected_version_is_checked_per_stream() {
+        let store = InMemoryMessageStore::new();
+
+        assert_eq!(store.write_message("account-1", "Opened", "{}", None, Some(-1)).await.unwrap(), 0);
+        assert_eq!(store.write_message("account-1", "Deposited", "{}", None, Some(0)).await.unwrap(), 1);
+        assert_eq!(store.write_message("account-2", "Opened", "{}", None, Some(-1)).await.unwrap(), 0);
+
+        match store.write_message("account-1", "Deposited", "{}", None, Some(0)).await {
+            Err(MessageStoreError::ExpectedVersion { stream_name, expected, actual }) => {
+                assert_eq!((stream_name.as_str(), expected, actual), ("account-1", 0, 1));
+            },
+            other => panic!("Expected a version conflict, got {:?}", other),
+        }
+        assert_eq!(store.stream_version("account-1").await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn global_positions_start_at_one_across_streams() {
+        let store = InMemoryMessageStore::new();
+        write(&store, "account-1").await;
+        write(&store, "account-2").await;
+        write(&store, "account-1").await;
+
+        assert_eq!(global_positions(&store.messages()), vec![1, 2, 3]);
+
+        let stream = store.get_stream_messages("account-1", None, None, None).await.unwrap();
+        assert_eq!(stream.iter().map(|message| message.position).collect::<Vec<_>>(), vec![Some(0), Some(1)]);
+
+        let category = store.get_category_messages("account", Some(2), None, None, None, None, None).await.unwrap();
+        assert_eq!(global_positions(&category), vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn consumer_group_members_partition_a_category_by_cardinal_id() {
+        let store = InMemoryMessageStore::new();
+        for id in 0..20 {
+            write(&store, &format!("account:commands-{}+retry", id)).await;
+            write(&store, &format!("account:commands-{}", id)).await;
+        }
+
+        let mut delivered = Vec::new();
+        for member in 0..3 {
+            let messages = store.get_category_messages("account:commands", None, None, None, Some(member), Some(3), None).await.unwrap();
+            for message in &messages {
+                // Compound ids are partitioned on their first id, alongside the plain stream
+                let cardinal_id = stream_name::cardinal_id(&message.stream_name).unwrap();
+                assert!(messages.iter().filter(|other| stream_name::cardinal_id(&other.stream_name) == Some(cardinal_id)).count() == 2);
+            }
+            delivered.extend(global_positions(&messages));
+        }
+
+        delivered.sort();
+        assert_eq!(delivered, (1..=40).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn consumer_groups_skip_streams_without_an_id() {
+        let store = InMemoryMessageStore::new();
+        // A stream without an id, which only a whole-category read can see
+        store.messages.lock().unwrap().push(Message {
+            stream_name: "account:commands".to_string(),
+            global_position: Some(1),
+            position: Some(0),
+            ..Message::default()
+        });
+
+        for member in 0..2 {
+            let messages = store.get_category_messages("account:commands", None, None, None, Some(member), Some(2), None).await.unwrap();
+            assert!(messages.is_empty());
+        }
+        let messages = store.get_category_messages("account:commands", None, None, None, None, None, None).await.unwrap();
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn correlation_matches_the_category_of_the_correlation_stream() {
+        let store = InMemoryMessageStore::new();
+        let correlated = Metadata { correlation_stream_name: Some("transfer-7".to_string()), ..Metadata::default() };
+        let other = Metadata { correlation_stream_name: Some("payment-7".to_string()), ..Metadata::default() };
+        store.write_message("account-1", "Deposited", "{}", Some(&correlated), None).await.unwrap();
+        store.write_message("account-2", "Deposited", "{}", Some(&other), None).await.unwrap();
+        store.write_message("account-3", "Deposited", "{}", None, None).await.unwrap();
+
+        let messages = store.get_category_messages("account", None, None, Some("transfer"), None, None, None).await.unwrap();
+        assert_eq!(global_positions(&messages), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn stream_and_category_names_are_checked() {
+        let store = InMemoryMessageStore::new();
+
+        assert!(matches!(
+            store.get_stream_messages("account:commands+dlq", None, None, None).await,
+            Err(MessageStoreError::InvalidStreamName(_))
+        ));
+        assert!(matches!(
+            store.get_category_messages("account-1", None, None, None, None, None, None).await,
+            Err(MessageStoreError::InvalidStreamName(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn an_empty_message_type_list_matches_nothing() {
+        let store = InMemoryMessageStore::new();
+        write(&store, "account-1").await;
+
+        let condition = Condition::message_type_in(&[]);
+        assert_eq!(condition.to_sql(), "FALSE");
+        let messages = store.get_stream_messages("account-1", None, None, Some(&condition)).await.unwrap();
+        assert!(messages.is_empty());
+    }
+}
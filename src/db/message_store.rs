@@ -1,193 +1,285 @@
-
-
-use std::pin::Pin;
 use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
 
-use tracing::{error, info, instrument, debug};
+use axum::async_trait;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, debug, warn};
 
-use crate::db;
+use crate::db::{Condition, MessageStoreError, SubscriptionError, SubscriptionSettings};
 use crate::messaging::message::Message;
+use crate::messaging::Metadata;
 
 #[derive(Debug, Clone)]
-pub struct MessageStore {
-    db: db::Db,
+pub struct NewMessage {
+    pub stream_name: String,
+    pub message_type: String,
+    pub data: String,
+    pub metadata: Option<Metadata>,
+}
+
+impl NewMessage {
+    pub fn new(stream_name: &str, message_type: &str, data: &str) -> Self {
+        NewMessage {
+            stream_name: stream_name.to_string(),
+            message_type: message_type.to_string(),
+            data: data.to_string(),
+            metadata: None,
+        }
+    }
+}
+
+/// Wakes a subscription when a message is written to the category it follows.
+#[async_trait]
+pub trait Notifications: Send {
+    /// Waits until a message is written to the category or the timeout elapses,
+    /// whichever comes first.
+    async fn wait(&mut self, timeout: Duration);
 }
 
-impl MessageStore {
-    pub fn new(db: db::Db) -> Self {
-        Self { db }
+/// Reads and writes messages with Message DB semantics: per-stream positions
+/// starting at 0, a store-wide global position, and optimistic concurrency via
+/// the expected version of a stream (`-1` for a stream with no messages).
+#[async_trait]
+pub trait MessageStore: Send + Sync {
+    async fn write_message(
+        &self,
+        stream_name: &str,
+        message_type: &str,
+        data: &str,
+        metadata: Option<&Metadata>,
+        expected_version: Option<i64>
+    ) -> Result<i64, MessageStoreError>;
+
+    /// Writes all messages atomically, returning their stream positions in order.
+    ///
+    /// `expected_version` applies to the first message. Later messages written to a stream
+    /// already touched by the batch are expected to follow the position written before them,
+    /// so the batch lands contiguously or not at all.
+    async fn write_batch(
+        &self,
+        messages: &[NewMessage],
+        expected_version: Option<i64>
+    ) -> Result<Vec<i64>, MessageStoreError>;
+
+    async fn get_stream_messages(
+        &self,
+        stream_name: &str,
+        position: Option<i64>,
+        batch_size: Option<i64>,
+        condition: Option<&Condition>
+    ) -> Result<Vec<Message>, MessageStoreError>;
+
+    #[allow(clippy::too_many_arguments)]
+    async fn get_category_messages(
+        &self,
+        category_name: &str,
+        position: Option<i64>,
+        batch_size: Option<i64>,
+        correlation: Option<&str>,
+        consumer_group_member: Option<i64>,
+        consumer_group_size: Option<i64>,
+        condition: Option<&Condition>
+    ) -> Result<Vec<Message>, MessageStoreError>;
+
+    async fn get_last_message(
+        &self,
+        stream_name: &str
+    ) -> Result<Option<Message>, MessageStoreError>;
+
+    /// Position of the last message in the stream, or `None` if the stream is empty.
+    async fn stream_version(
+        &self,
+        stream_name: &str
+    ) -> Result<Option<i64>, MessageStoreError>;
+
+    /// Stores that cannot push notifications return `None` and subscriptions poll.
+    async fn notifications(
+        &self,
+        _category: &str
+    ) -> Result<Option<Box<dyn Notifications>>, MessageStoreError> {
+        Ok(None)
+    }
+}
+
+/// Where a paged read resumes: the stream position for stream reads,
+/// the global position for category reads.
+#[derive(Clone, Copy)]
+enum Page {
+    Next(i64),
+    Done,
+}
+
+impl dyn MessageStore {
+    /// Reads every message in the stream from `position` onwards, fetching
+    /// `batch_size` messages at a time until the end of the stream.
+    pub fn read_stream<'a>(
+        &'a self,
+        stream_name: &'a str,
+        position: Option<i64>,
+        batch_size: i64,
+    ) -> BoxStream<'a, Result<Message, MessageStoreError>> {
+        let pages = stream::try_unfold(Page::Next(position.unwrap_or(0)), move |page| async move {
+            let position = match page {
+                Page::Next(position) => position,
+                Page::Done => return Ok::<_, MessageStoreError>(None),
+            };
+
+            let messages = self.get_stream_messages(stream_name, Some(position), Some(batch_size), None).await?;
+            let next = match messages.last() {
+                Some(last) if messages.len() as i64 >= batch_size => {
+                    Page::Next(last.position.unwrap_or(position) + 1)
+                },
+                _ => Page::Done,
+            };
+            debug!("Read {} messages from {} at position {}", messages.len(), stream_name, position);
+
+            Ok(Some((messages, next)))
+        });
+
+        pages
+            .map_ok(|messages| stream::iter(messages.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    /// Reads every message in the category from global `position` onwards,
+    /// fetching `batch_size` messages at a time until the end of the category.
+    pub fn read_category<'a>(
+        &'a self,
+        category_name: &'a str,
+        position: Option<i64>,
+        batch_size: i64,
+    ) -> BoxStream<'a, Result<Message, MessageStoreError>> {
+        let pages = stream::try_unfold(Page::Next(position.unwrap_or(1)), move |page| async move {
+            let position = match page {
+                Page::Next(position) => position,
+                Page::Done => return Ok::<_, MessageStoreError>(None),
+            };
+
+            let messages = self.get_category_messages(
+                category_name, Some(position), Some(batch_size), None, None, None, None
+            ).await?;
+            let next = match messages.last() {
+                Some(last) if messages.len() as i64 >= batch_size => {
+                    Page::Next(last.global_position.unwrap_or(position) + 1)
+                },
+                _ => Page::Done,
+            };
+            debug!("Read {} messages from {} at global position {}", messages.len(), category_name, position);
+
+            Ok(Some((messages, next)))
+        });
+
+        pages
+            .map_ok(|messages| stream::iter(messages.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
     }
 
     pub async fn subscribe_to_stream<F>(
         &self,
         stream_name: &str,
         starting_position: i64,
+        settings: &SubscriptionSettings,
+        shutdown: &CancellationToken,
         mut f: F,
-    ) where
-        F: FnMut(Message) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + 'static,
+    ) -> Result<(), SubscriptionError>
+    where
+        F: FnMut(Message) -> Pin<Box<dyn Future<Output = Result<(), SubscriptionError>> + Send>> + Send + 'static,
     {
+        let mut notifications = if settings.notifications {
+            match self.notifications(stream_name).await {
+                Ok(notifications) => notifications,
+                Err(e) => {
+                    warn!("Failed to listen for notifications, polling every {:?}: {}", settings.poll_interval, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let (consumer_group_member, consumer_group_size) = match settings.consumer_group {
+            Some(group) => {
+                info!("Subscribing to {} as consumer group member {} of {}", stream_name, group.member, group.size);
+                (Some(group.member), Some(group.size))
+            },
+            None => (None, None),
+        };
+
         let mut last_position = starting_position;
+        let mut failures = 0;
         loop {
-            let messages = self.get_category_messages(stream_name, Some(last_position+1), None, None, None, None, None).await;
+            let messages = self.get_category_messages(
+                stream_name,
+                Some(last_position+1),
+                Some(settings.batch_size),
+                settings.correlation.as_deref(),
+                consumer_group_member,
+                consumer_group_size,
+                None
+            ).await;
+            if messages.is_ok() {
+                failures = 0;
+            }
             match messages {
                 Ok(messages) if !messages.is_empty() => {
+                    let batch_full = messages.len() as i64 >= settings.batch_size;
                     for message in messages {
+                        // Stop between messages so the one in flight always completes
+                        if shutdown.is_cancelled() {
+                            info!("Subscription to {} stopped at position {}", stream_name, last_position);
+                            return Ok(());
+                        }
                         last_position = message.global_position.unwrap_or(last_position);
                         debug!("Dispatching message with position {}: {:?}", last_position, message);
-                        f(message).await;
+                        if let Err(e) = f(message).await {
+                            error!("Subscription to {} stopped at position {}: {}", stream_name, last_position, e);
+                            return Err(e);
+                        }
                         debug!("Message with position {} handled successfully", last_position);
                     }
+                    if batch_full {
+                        // More messages are likely waiting, so read again right away
+                        continue;
+                    }
                 },
                 Ok(_) => {
                     debug!("No new messages at position {}", last_position);
                 },
+                Err(e) if e.is_transient() => {
+                    failures += 1;
+                    if settings.retry.exhausted(failures) {
+                        error!("Failed to fetch messages {} times in a row, giving up: {}", failures, e);
+                        return Err(e.into());
+                    }
+                    let backoff = settings.retry.backoff(failures);
+                    warn!("Failed to fetch messages (attempt {}), retrying in {:?}: {}", failures, backoff, e);
+                    tokio::select! {
+                        _ = shutdown.cancelled() => return Ok(()),
+                        _ = tokio::time::sleep(backoff) => continue,
+                    }
+                },
                 Err(e) => {
                     error!("Failed to fetch messages: {}", e);
-                    break; // or handle error appropriately
+                    return Err(e.into());
                 }
             }
-            // sleep a few seconds
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-        }
-    }
-
-
-    #[instrument]
-    pub async fn get_stream_messages(
-        &self,
-        stream_name: &str,
-        position: Option<i64>,
-        batch_size: Option<i64>,
-        condition: Option<&str>
-    ) -> Result<Vec<Message>, sqlx::Error> {
-        if condition.is_some() {
-            error!("Condition is not supported for category messages");
-            return Err(sqlx::Error::Protocol("Condition is not supported for category messages".to_string()));
-        }
-        let db = &self.db;
-
-        let query = r#"
-            SELECT global_position, position, type AS message_type, data, metadata, time
-            FROM get_stream_messages($1::varchar, $2::bigint, $3::bigint, NULL::varchar);
-        "#;
-
-        let messages = sqlx::query_as::<_, Message>(query)
-            .bind(stream_name)
-            .bind(position.unwrap_or(0))
-            .bind(batch_size.unwrap_or(1000))
-            .fetch_all(db.pool())
-            .await;
-
-        match messages {
-            Ok(messages) => {
-                info!("Messages fetched successfully.");
-                Ok(messages)
-            },
-            Err(e) => {
-                error!("Failed to fetch messages: {}", e);
-                Err(e)
-            }
-        }
-    }
-
 
-    #[instrument]
-    async fn get_category_messages(
-        &self,
-        category_name: &str,
-        position: Option<i64>,
-        batch_size: Option<i64>,
-        correlation: Option<&str>,
-        consumer_group_member: Option<i64>,
-        consumer_group_size: Option<i64>,
-        condition: Option<&str>
-    ) -> Result<Vec<Message>, sqlx::Error> {
-        if condition.is_some() {
-            error!("Condition is not supported for category messages");
-            return Err(sqlx::Error::Protocol("Condition is not supported for category messages".to_string()));
-        }
-        let db = &self.db;
-        let query = r#"
-            SELECT global_position, position, type AS message_type, data, metadata, time
-            FROM get_category_messages($1, $2, $3, $4, $5, $6, NULL);
-        "#;
-
-        let messages = sqlx::query_as::<_, Message>(query)
-            .bind(category_name)
-            .bind(position.unwrap_or(0))  // Default to 0 if None
-            .bind(batch_size.unwrap_or(1000))  // Default to 1000 if None
-            .bind(correlation)  // Properly pass None as SQL NULL
-            .bind(consumer_group_member)
-            .bind(consumer_group_size)
-
-            .fetch_all(db.pool())
-            .await;
-
-        match messages {
-            Ok(messages) => {
-                info!("Category messages fetched successfully.");
-                Ok(messages)
-            },
-            Err(e) => {
-                error!("Failed to fetch category messages: {}", e);
-                Err(e)
-            }
-        }
-    }
-
-    #[instrument]
-    pub async fn write_message(
-        &self,
-        stream_name: &str,
-        message_type: &str,
-        data: &str,
-        metadata: Option<&str>,  // Optional, can be None
-        expected_version: Option<i64>  // Optional, can be None for new streams or first message
-    ) {
-        let db = &self.db;
-        let message_id = uuid::Uuid::new_v4();
-        let query = r#"
-            SELECT write_message($1::varchar, $2::varchar, $3::varchar, $4::jsonb, $5::jsonb, $6::bigint);
-        "#;
-        let result = sqlx::query(query)
-            .bind(message_id.to_string())
-            .bind(stream_name)
-            .bind(message_type)
-            .bind(data)
-            .bind(metadata.unwrap_or("null"))
-            .bind(expected_version)
-            .execute(db.pool())
-            .await;
-
-        match result {
-            Ok(_) => info!("Message written successfully"),
-            Err(e) => error!("Failed to write message: {}", e),
-        }
-    }
-
-    pub async fn get_last_message(
-        &self,
-        stream_name: &str
-    ) -> Result<Option<Message>, sqlx::Error> {
-        let db = &self.db;
-        let query = r#"
-            SELECT global_position, position, type AS message_type, data, metadata, time
-            FROM get_last_stream_message($1::varchar);
-        "#;
-
-        let message = sqlx::query_as::<_, Message>(query)
-            .bind(stream_name)
-            .fetch_optional(db.pool())
-            .await;
-
-        match message {
-            Ok(message) => {
-                info!("Last message fetched successfully.");
-                Ok(message)
-            },
-            Err(e) => {
-                error!("Failed to fetch last message: {}", e);
-                Err(e)
+            let wait = async {
+                match notifications.as_mut() {
+                    Some(notifications) => notifications.wait(settings.poll_interval).await,
+                    None => tokio::time::sleep(settings.poll_interval).await,
+                }
+            };
+            tokio::select! {
+                _ = shutdown.cancelled() => {
+                    info!("Subscription to {} stopped at position {}", stream_name, last_position);
+                    return Ok(());
+                },
+                _ = wait => {},
             }
         }
     }
@@ -1,6 +1,16 @@
 pub mod postgres;
 pub mod message_store;
+pub mod postgres_message_store;
+pub mod memory_message_store;
+pub mod error;
+pub mod condition;
+pub mod subscription;
 
 // Re-export key components
 pub use self::postgres::Db;
-pub use self::message_store::MessageStore;
+pub use self::message_store::{MessageStore, NewMessage, Notifications};
+pub use self::postgres_message_store::PostgresMessageStore;
+pub use self::memory_message_store::InMemoryMessageStore;
+pub use self::error::MessageStoreError;
+pub use self::condition::Condition;
+pub use self::subscription::{ConsumerGroup, SubscriptionError, SubscriptionSettings};
@@ -1,9 +1,7 @@
-use sqlx::{Pool, Postgres, postgres::PgPoolOptions, Error as SqlxError};
+use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
 use sqlx::Executor;
 use axum::async_trait;
 use tracing::{info, instrument};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
 
 #[async_trait]
 pub trait Database {
@@ -0,0 +1,284 @@
+
+
+use std::collections::HashMap;
+
+use axum::async_trait;
+use sqlx::Executor;
+use tracing::{error, info, instrument};
+
+use crate::db;
+use crate::db::{Condition, MessageStore, MessageStoreError, NewMessage, Notifications};
+use crate::db::subscription::{NotificationListener, NOTIFY_TRIGGER_SQL};
+use crate::messaging::message::Message;
+use crate::messaging::Metadata;
+
+const WRITE_MESSAGE_QUERY: &str = r#"
+    SELECT write_message($1::varchar, $2::varchar, $3::varchar, $4::jsonb, $5::jsonb, $6::bigint);
+"#;
+
+fn metadata_json(metadata: Option<&Metadata>) -> Result<String, MessageStoreError> {
+    match metadata {
+        Some(metadata) if !metadata.is_empty() => Ok(metadata.to_json()?),
+        _ => Ok("null".to_string()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PostgresMessageStore {
+    db: db::Db,
+}
+
+impl PostgresMessageStore {
+    pub fn new(db: db::Db) -> Self {
+        Self { db }
+    }
+
+    /// Installs the trigger that notifies subscribers when a message is written,
+    /// unless it already exists. Creating it locks `message_store.messages` and needs
+    /// DDL rights, so this is run once by the `install_notification_trigger` binary
+    /// rather than on every start.
+    pub async fn install_notification_trigger(&self) -> Result<(), MessageStoreError> {
+        if self.notification_trigger_installed().await? {
+            info!("Notification trigger already installed");
+            return Ok(());
+        }
+
+        // Sent as one simple query, so the function and trigger are created in one transaction
+        self.db.pool().execute(NOTIFY_TRIGGER_SQL).await?;
+        info!("Notification trigger installed");
+        Ok(())
+    }
+
+    pub async fn notification_trigger_installed(&self) -> Result<bool, MessageStoreError> {
+        let query = r#"
+            SELECT EXISTS (
+                SELECT 1 FROM pg_trigger
+                WHERE tgname = 'notify_message_written'
+                  AND tgrelid = 'message_store.messages'::regclass
+            );
+        "#;
+        let installed = sqlx::query_scalar(query).fetch_one(self.db.pool()).await?;
+        Ok(installed)
+    }
+}
+
+#[async_trait]
+impl MessageStore for PostgresMessageStore {
+    #[instrument]
+    async fn get_stream_messages(
+        &self,
+        stream_name: &str,
+        position: Option<i64>,
+        batch_size: Option<i64>,
+        condition: Option<&Condition>
+    ) -> Result<Vec<Message>, MessageStoreError> {
+        let db = &self.db;
+
+        let query = r#"
+            SELECT id, stream_name, global_position, position, type AS message_type, data, metadata, time
+            FROM get_stream_messages($1::varchar, $2::bigint, $3::bigint, $4::varchar);
+        "#;
+
+        let messages = sqlx::query_as::<_, Message>(query)
+            .bind(stream_name)
+            .bind(position.unwrap_or(0))
+            .bind(batch_size.unwrap_or(1000))
+            .bind(condition.map(Condition::to_sql))
+            .fetch_all(db.pool())
+            .await;
+
+        match messages {
+            Ok(messages) => {
+                info!("Messages fetched successfully.");
+                Ok(messages)
+            },
+            Err(e) => {
+                error!("Failed to fetch messages: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+
+    #[instrument]
+    async fn get_category_messages(
+        &self,
+        category_name: &str,
+        position: Option<i64>,
+        batch_size: Option<i64>,
+        correlation: Option<&str>,
+        consumer_group_member: Option<i64>,
+        consumer_group_size: Option<i64>,
+        condition: Option<&Condition>
+    ) -> Result<Vec<Message>, MessageStoreError> {
+        let db = &self.db;
+        let query = r#"
+            SELECT id, stream_name, global_position, position, type AS message_type, data, metadata, time
+            FROM get_category_messages($1, $2, $3, $4, $5, $6, $7);
+        "#;
+
+        let messages = sqlx::query_as::<_, Message>(query)
+            .bind(category_name)
+            .bind(position.unwrap_or(0))  // Default to 0 if None
+            .bind(batch_size.unwrap_or(1000))  // Default to 1000 if None
+            .bind(correlation)  // Properly pass None as SQL NULL
+            .bind(consumer_group_member)
+            .bind(consumer_group_size)
+            .bind(condition.map(Condition::to_sql))
+            .fetch_all(db.pool())
+            .await;
+
+        match messages {
+            Ok(messages) => {
+                info!("Category messages fetched successfully.");
+                Ok(messages)
+            },
+            Err(e) => {
+                error!("Failed to fetch category messages: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    #[instrument]
+    async fn write_message(
+        &self,
+        stream_name: &str,
+        message_type: &str,
+        data: &str,
+        metadata: Option<&Metadata>,  // Optional, can be None
+        expected_version: Option<i64>  // Optional, can be None for new streams or first message
+    ) -> Result<i64, MessageStoreError> {
+        let db = &self.db;
+        let metadata = metadata_json(metadata)?;
+        let result = sqlx::query_scalar::<_, i64>(WRITE_MESSAGE_QUERY)
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(stream_name)
+            .bind(message_type)
+            .bind(data)
+            .bind(metadata)
+            .bind(expected_version)
+            .fetch_one(db.pool())
+            .await;
+
+        match result {
+            Ok(position) => {
+                info!("Message written successfully at position {}", position);
+                Ok(position)
+            },
+            Err(e) => {
+                let e = MessageStoreError::from(e);
+                error!("Failed to write message: {}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Writes all messages in a single transaction, so a conflict on any of them rolls back the batch.
+    #[instrument(skip(messages), fields(count = messages.len()))]
+    async fn write_batch(
+        &self,
+        messages: &[NewMessage],
+        expected_version: Option<i64>
+    ) -> Result<Vec<i64>, MessageStoreError> {
+        let db = &self.db;
+        let mut tx = db.pool().begin().await?;
+        let mut positions = Vec::with_capacity(messages.len());
+        let mut written: HashMap<&str, i64> = HashMap::new();
+
+        for (index, message) in messages.iter().enumerate() {
+            let expected = match written.get(message.stream_name.as_str()) {
+                Some(position) => Some(*position),
+                None if index == 0 => expected_version,
+                None => None,
+            };
+
+            let metadata = metadata_json(message.metadata.as_ref())?;
+            let result = sqlx::query_scalar::<_, i64>(WRITE_MESSAGE_QUERY)
+                .bind(uuid::Uuid::new_v4().to_string())
+                .bind(&message.stream_name)
+                .bind(&message.message_type)
+                .bind(&message.data)
+                .bind(metadata)
+                .bind(expected)
+                .fetch_one(&mut *tx)
+                .await;
+
+            match result {
+                Ok(position) => {
+                    written.insert(message.stream_name.as_str(), position);
+                    positions.push(position);
+                },
+                Err(e) => {
+                    let e = MessageStoreError::from(e);
+                    error!("Failed to write batch message {} to {}, rolling back: {}", index, message.stream_name, e);
+                    tx.rollback().await?;
+                    return Err(e);
+                }
+            }
+        }
+
+        tx.commit().await?;
+        info!("Batch of {} messages written successfully", positions.len());
+        Ok(positions)
+    }
+
+    async fn get_last_message(
+        &self,
+        stream_name: &str
+    ) -> Result<Option<Message>, MessageStoreError> {
+        let db = &self.db;
+        let query = r#"
+            SELECT id, stream_name, global_position, position, type AS message_type, data, metadata, time
+            FROM get_last_stream_message($1::varchar);
+        "#;
+
+        let message = sqlx::query_as::<_, Message>(query)
+            .bind(stream_name)
+            .fetch_optional(db.pool())
+            .await;
+
+        match message {
+            Ok(message) => {
+                info!("Last message fetched successfully.");
+                Ok(message)
+            },
+            Err(e) => {
+                error!("Failed to fetch last message: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    #[instrument]
+    async fn stream_version(
+        &self,
+        stream_name: &str
+    ) -> Result<Option<i64>, MessageStoreError> {
+        let db = &self.db;
+        let query = r#"
+            SELECT stream_version($1::varchar);
+        "#;
+
+        let version = sqlx::query_scalar::<_, Option<i64>>(query)
+            .bind(stream_name)
+            .fetch_one(db.pool())
+            .await;
+
+        match version {
+            Ok(version) => Ok(version),
+            Err(e) => {
+                error!("Failed to fetch stream version: {}", e);
+                Err(e.into())
+            }
+        }
+    }
+
+    async fn notifications(
+        &self,
+        category: &str
+    ) -> Result<Option<Box<dyn Notifications>>, MessageStoreError> {
+        let listener = NotificationListener::connect(&self.db, category).await?;
+        Ok(Some(Box::new(listener)))
+    }
+}
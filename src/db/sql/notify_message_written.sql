@@ -0,0 +1,13 @@
+CREATE OR REPLACE FUNCTION message_store.notify_message_written()
+RETURNS trigger
+AS $$
+BEGIN
+  PERFORM pg_notify('message_store_messages', message_store.category(NEW.stream_name));
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_message_written
+  AFTER INSERT ON message_store.messages
+  FOR EACH ROW
+  EXECUTE FUNCTION message_store.notify_message_written();
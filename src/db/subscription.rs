@@ -0,0 +1,140 @@
+use std::fmt;
+use std::time::Duration;
+
+use axum::async_trait;
+use sqlx::postgres::PgListener;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::db;
+use crate::db::{MessageStoreError, Notifications};
+use crate::util::RetryPolicy;
+
+/// Channel the `notify_message_written` trigger publishes to; the payload is the category name.
+pub const NOTIFY_CHANNEL: &str = "message_store_messages";
+
+pub const NOTIFY_TRIGGER_SQL: &str = include_str!("sql/notify_message_written.sql");
+
+/// One member of a group of consumers that hash-partition a category between them.
+/// Members are numbered from 0 to `size - 1`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsumerGroup {
+    pub member: i64,
+    pub size: i64,
+}
+
+impl ConsumerGroup {
+    pub fn new(member: i64, size: i64) -> Result<Self, String> {
+        if size < 1 {
+            return Err(format!("Consumer group size must be at least 1, got {}", size));
+        }
+        if member < 0 || member >= size {
+            return Err(format!("Consumer group member must be between 0 and {}, got {}", size - 1, member));
+        }
+
+        Ok(ConsumerGroup { member, size })
+    }
+
+    /// Identifier for the member's position stream, e.g. `account:commands:position-1`.
+    pub fn identifier(&self) -> String {
+        self.member.to_string()
+    }
+}
+
+/// Why a subscription stopped.
+#[derive(Debug)]
+pub enum SubscriptionError {
+    /// Reading from the store failed permanently or too many times in a row.
+    Read(MessageStoreError),
+    /// The message callback asked the subscription to stop for good, e.g. the
+    /// consumer's error policy halts on messages it cannot handle.
+    Halted(String),
+    /// The message callback could not finish a message for now, e.g. the store was
+    /// unavailable. Subscribing again from the last position retries it.
+    Interrupted(String),
+}
+
+impl fmt::Display for SubscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriptionError::Read(e) => write!(f, "Failed to read messages: {}", e),
+            SubscriptionError::Halted(reason) => write!(f, "Halted: {}", reason),
+            SubscriptionError::Interrupted(reason) => write!(f, "Interrupted: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SubscriptionError {}
+
+impl From<MessageStoreError> for SubscriptionError {
+    fn from(e: MessageStoreError) -> Self {
+        SubscriptionError::Read(e)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriptionSettings {
+    pub batch_size: i64,
+    pub consumer_group: Option<ConsumerGroup>,
+    /// Only receive messages whose `metadata.correlationStreamName` is in this category.
+    pub correlation: Option<String>,
+    /// How long to wait between polls when no notification arrives.
+    pub poll_interval: Duration,
+    /// Wake on `LISTEN`/`NOTIFY` instead of only polling.
+    pub notifications: bool,
+    /// Backoff between failed reads, and how many in a row end the subscription.
+    pub retry: RetryPolicy,
+}
+
+impl Default for SubscriptionSettings {
+    fn default() -> Self {
+        SubscriptionSettings {
+            batch_size: 1000,
+            consumer_group: None,
+            correlation: None,
+            poll_interval: Duration::from_secs(5),
+            notifications: true,
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+pub struct NotificationListener {
+    listener: PgListener,
+    category: String,
+}
+
+impl NotificationListener {
+    pub async fn connect(db: &db::Db, category: &str) -> Result<Self, MessageStoreError> {
+        let mut listener = PgListener::connect_with(db.pool()).await?;
+        listener.listen(NOTIFY_CHANNEL).await?;
+        debug!("Listening on {} for category {}", NOTIFY_CHANNEL, category);
+
+        Ok(NotificationListener { listener, category: category.to_string() })
+    }
+
+}
+
+#[async_trait]
+impl Notifications for NotificationListener {
+    // Notifications for other categories are ignored.
+    async fn wait(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match tokio::time::timeout_at(deadline, self.listener.recv()).await {
+                Ok(Ok(notification)) if notification.payload() == self.category => {
+                    debug!("Notified of new message in {}", self.category);
+                    return;
+                },
+                Ok(Ok(_)) => continue,
+                Ok(Err(e)) => {
+                    // PgListener reconnects on the next recv; poll until then.
+                    warn!("Notification listener failed, falling back to polling: {}", e);
+                    tokio::time::sleep_until(deadline).await;
+                    return;
+                },
+                Err(_) => return,
+            }
+        }
+    }
+}
@@ -1,10 +1,27 @@
 use chrono::NaiveDateTime;
 
+use crate::domain::money::{Money, MoneyError};
+use crate::messaging::StreamName;
+
+pub const CATEGORY: &str = "account";
+pub const COMMANDS_TYPE: &str = "commands";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+    Open,
+    Closed,
+}
+
 pub struct Account {
     pub id: String,
+    /// `None` until the account is opened, and for `Opened` events written without a time.
     pub opened_time: Option<NaiveDateTime>,
-    pub balance: Option<i64>,
-    pub status: Option<String>,
+    /// `None` until the first deposit, which also sets the account's currency.
+    pub balance: Option<Money>,
+    /// `None` until the account is opened.
+    pub status: Option<AccountStatus>,
+    /// Global position of the last command applied to the account.
+    pub sequence: Option<i64>,
 }
 
 impl Account {
@@ -14,10 +31,61 @@ impl Account {
             opened_time: None,
             balance: None,
             status: None,
+            sequence: None,
         }
     }
 
+    pub fn stream_name(id: &str) -> StreamName {
+        StreamName::entity(CATEGORY, id)
+    }
+
+    pub fn commands_category() -> StreamName {
+        StreamName::category_with_type(CATEGORY, COMMANDS_TYPE)
+    }
+
+    /// Stream to write an account's commands to, e.g. `account:commands-123`. Consumers
+    /// subscribe to `commands_category()`; the id is what consumer groups partition on.
+    pub fn commands_stream_name(id: &str) -> StreamName {
+        StreamName::entity(Account::commands_category().as_str(), id)
+    }
+
+    /// Whether the account was ever opened, including accounts since closed.
     pub fn opened(&self) -> bool {
-        self.opened_time.is_some()
+        self.status.is_some()
+    }
+
+    pub fn closed(&self) -> bool {
+        self.status == Some(AccountStatus::Closed)
+    }
+
+    /// Whether the command at this global position has already been applied.
+    pub fn processed(&self, sequence: i64) -> bool {
+        self.sequence.is_some_and(|applied| applied >= sequence)
+    }
+
+    /// Whether there is money left in the account.
+    pub fn has_balance(&self) -> bool {
+        self.balance.is_some_and(|balance| !balance.is_zero())
+    }
+
+    /// Fails if the amount is in another currency than the balance, or the
+    /// balance would overflow.
+    pub fn balance_after_deposit(&self, amount: &Money) -> Result<Money, MoneyError> {
+        self.balance.unwrap_or(Money::zero(amount.currency())).checked_add(amount)
+    }
+
+    /// Fails with `MoneyError::Negative` when the balance does not cover the amount.
+    pub fn balance_after_withdrawal(&self, amount: &Money) -> Result<Money, MoneyError> {
+        self.balance.unwrap_or(Money::zero(amount.currency())).checked_sub(amount)
+    }
+
+    pub fn deposit(&mut self, amount: &Money) -> Result<(), MoneyError> {
+        self.balance = Some(self.balance_after_deposit(amount)?);
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self, amount: &Money) -> Result<(), MoneyError> {
+        self.balance = Some(self.balance_after_withdrawal(amount)?);
+        Ok(())
     }
 }
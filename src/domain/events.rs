@@ -1,54 +1,84 @@
-use crate::messaging::commands::Command;
-use crate::messaging::events::Event;
-use crate::messaging::Message;
+use crate::domain::money::Money;
 use chrono::NaiveDateTime;
-use serde_json::Value;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Opened {
     pub account_id: String,
     pub processed_time: Option<NaiveDateTime>,
-    #[serde(skip_serializing)]
-    pub position: Option<i64>,
-    #[serde(skip_serializing)]
-    pub message: Message,
 }
 
-impl Event for Opened {
-    fn follow(command: &dyn Command) -> Self {
-        Opened {
-            account_id: command.account_id().to_string(),
-            processed_time: None,
-            position: command.position(),
-            message: command.message().clone(),
-        }
-    }
-
-    fn from_message(message: Message) -> Result<Self, String> {
-        let data: Value = serde_json::from_str(&message.data)
-            .map_err(|e| format!("Failed to parse JSON data: {}", e))?;
+/// `sequence` is the global position of the `Deposit` command, so the command
+/// is not applied twice if it is handled again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deposited {
+    pub account_id: String,
+    pub amount: Money,
+    pub processed_time: Option<NaiveDateTime>,
+    pub sequence: i64,
+}
 
-        let account_id = data["account_id"]
-            .as_str()
-            .ok_or("Missing account_id in message data")?
-            .to_string();
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepositRejected {
+    pub account_id: String,
+    pub amount: Money,
+    pub reason: String,
+    pub processed_time: Option<NaiveDateTime>,
+    pub sequence: i64,
+}
 
-        let processed_time = data["processed_time"]
-            .as_str()
-            .map(|s| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f").unwrap());
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawn {
+    pub account_id: String,
+    pub amount: Money,
+    pub processed_time: Option<NaiveDateTime>,
+    pub sequence: i64,
+}
 
-        let position = message.position;
+/// `balance` is the account's balance when the withdrawal was rejected, `None`
+/// if nothing was ever deposited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawalRejected {
+    pub account_id: String,
+    pub amount: Money,
+    pub balance: Option<Money>,
+    pub reason: String,
+    pub processed_time: Option<NaiveDateTime>,
+    pub sequence: i64,
+}
 
-        Ok(Opened { account_id, processed_time, position, message })
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpenRejected {
+    pub account_id: String,
+    pub reason: String,
+    pub processed_time: Option<NaiveDateTime>,
+    pub sequence: i64,
+}
 
-    fn message(&self) -> &Message {
-        &self.message
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Closed {
+    pub account_id: String,
+    pub processed_time: Option<NaiveDateTime>,
+    pub sequence: i64,
+}
 
-    fn event_name(&self) -> &'static str {
-        "Opened"
-    }
+/// `balance` is the account's balance when closing was rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloseRejected {
+    pub account_id: String,
+    pub balance: Option<Money>,
+    pub reason: String,
+    pub processed_time: Option<NaiveDateTime>,
+    pub sequence: i64,
 }
+
+crate::message_data!(Event,
+    Opened,
+    Deposited,
+    DepositRejected,
+    Withdrawn,
+    WithdrawalRejected,
+    OpenRejected,
+    Closed,
+    CloseRejected,
+);
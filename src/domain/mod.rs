@@ -2,6 +2,7 @@ pub mod commands;
 pub mod events;
 pub mod stores;
 pub mod account;
+pub mod money;
 
 
 
@@ -0,0 +1,317 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    /// Not an active ISO 4217 code.
+    InvalidCurrency(String),
+    /// Not a plain decimal such as `12.34`.
+    InvalidAmount(String),
+    /// More decimal places than the currency has minor units, e.g. `1.005` USD.
+    FractionalMinorUnit { amount: String, currency: Currency },
+    Negative(String),
+    CurrencyMismatch { expected: Currency, actual: Currency },
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::InvalidCurrency(code) => write!(f, "Invalid currency code: {}", code),
+            MoneyError::InvalidAmount(amount) => write!(f, "Invalid amount: {}", amount),
+            MoneyError::FractionalMinorUnit { amount, currency } => {
+                write!(f, "Amount {} has more than {} decimal places for {}", amount, currency.minor_unit_digits(), currency)
+            },
+            MoneyError::Negative(amount) => write!(f, "Amount must not be negative: {}", amount),
+            MoneyError::CurrencyMismatch { expected, actual } => write!(f, "Expected {}, got {}", expected, actual),
+            MoneyError::Overflow => write!(f, "Amount is out of range"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+/// Active ISO 4217 codes and the decimal places of their minor unit, sorted by
+/// code. Precious metals, testing and no-currency codes (`XAU`, `XTS`, `XXX`)
+/// have no minor unit and are left out.
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AED", 2), ("AFN", 2), ("ALL", 2), ("AMD", 2), ("ANG", 2), ("AOA", 2), ("ARS", 2), ("AUD", 2),
+    ("AWG", 2), ("AZN", 2), ("BAM", 2), ("BBD", 2), ("BDT", 2), ("BGN", 2), ("BHD", 3), ("BIF", 0),
+    ("BMD", 2), ("BND", 2), ("BOB", 2), ("BOV", 2), ("BRL", 2), ("BSD", 2), ("BTN", 2), ("BWP", 2),
+    ("BYN", 2), ("BZD", 2), ("CAD", 2), ("CDF", 2), ("CHE", 2), ("CHF", 2), ("CHW", 2), ("CLF", 4),
+    ("CLP", 0), ("CNY", 2), ("COP", 2), ("COU", 2), ("CRC", 2), ("CUP", 2), ("CVE", 2), ("CZK", 2),
+    ("DJF", 0), ("DKK", 2), ("DOP", 2), ("DZD", 2), ("EGP", 2), ("ERN", 2), ("ETB", 2), ("EUR", 2),
+    ("FJD", 2), ("FKP", 2), ("GBP", 2), ("GEL", 2), ("GHS", 2), ("GIP", 2), ("GMD", 2), ("GNF", 0),
+    ("GTQ", 2), ("GYD", 2), ("HKD", 2), ("HNL", 2), ("HTG", 2), ("HUF", 2), ("IDR", 2), ("ILS", 2),
+    ("INR", 2), ("IQD", 3), ("IRR", 2), ("ISK", 0), ("JMD", 2), ("JOD", 3), ("JPY", 0), ("KES", 2),
+    ("KGS", 2), ("KHR", 2), ("KMF", 0), ("KPW", 2), ("KRW", 0), ("KWD", 3), ("KYD", 2), ("KZT", 2),
+    ("LAK", 2), ("LBP", 2), ("LKR", 2), ("LRD", 2), ("LSL", 2), ("LYD", 3), ("MAD", 2), ("MDL", 2),
+    ("MGA", 2), ("MKD", 2), ("MMK", 2), ("MNT", 2), ("MOP", 2), ("MRU", 2), ("MUR", 2), ("MVR", 2),
+    ("MWK", 2), ("MXN", 2), ("MXV", 2), ("MYR", 2), ("MZN", 2), ("NAD", 2), ("NGN", 2), ("NIO", 2),
+    ("NOK", 2), ("NPR", 2), ("NZD", 2), ("OMR", 3), ("PAB", 2), ("PEN", 2), ("PGK", 2), ("PHP", 2),
+    ("PKR", 2), ("PLN", 2), ("PYG", 0), ("QAR", 2), ("RON", 2), ("RSD", 2), ("RUB", 2), ("RWF", 0),
+    ("SAR", 2), ("SBD", 2), ("SCR", 2), ("SDG", 2), ("SEK", 2), ("SGD", 2), ("SHP", 2), ("SLE", 2),
+    ("SOS", 2), ("SRD", 2), ("SSP", 2), ("STN", 2), ("SVC", 2), ("SYP", 2), ("SZL", 2), ("THB", 2),
+    ("TJS", 2), ("TMT", 2), ("TND", 3), ("TOP", 2), ("TRY", 2), ("TTD", 2), ("TWD", 2), ("TZS", 2),
+    ("UAH", 2), ("UGX", 0), ("USD", 2), ("USN", 2), ("UYI", 0), ("UYU", 2), ("UYW", 4), ("UZS", 2),
+    ("VED", 2), ("VES", 2), ("VND", 0), ("VUV", 0), ("WST", 2), ("XAF", 0), ("XCD", 2), ("XCG", 2),
+    ("XOF", 0), ("XPF", 0), ("YER", 2), ("ZAR", 2), ("ZMW", 2), ("ZWG", 2),
+];
+
+/// An active ISO 4217 currency code such as `USD`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn new(code: &str) -> Result<Currency, MoneyError> {
+        match code.as_bytes() {
+            &[a, b, c] if Currency::lookup(code).is_some() => Ok(Currency([a, b, c])),
+            _ => Err(MoneyError::InvalidCurrency(code.to_string())),
+        }
+    }
+
+    fn lookup(code: &str) -> Option<u32> {
+        CURRENCIES.binary_search_by_key(&code, |(known, _)| known).ok()
+            .map(|index| CURRENCIES[index].1)
+    }
+
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    /// Decimal places of the currency's minor unit, e.g. 2 for USD cents and 0 for JPY.
+    pub fn minor_unit_digits(&self) -> u32 {
+        Currency::lookup(self.code()).expect("currencies are only created from known codes")
+    }
+
+    fn minor_units_per_unit(&self) -> i64 {
+        10_i64.pow(self.minor_unit_digits())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Currency").field(&self.code()).finish()
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// A non-negative amount of one currency, held as a whole number of its minor
+/// unit (cents for USD). In JSON the amount is a decimal string, so it never
+/// passes through a float:
+///
+/// ```json
+/// { "amount": "12.34", "currency": "USD" }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "MoneyJson", into = "MoneyJson")]
+pub struct Money {
+    minor_units: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Result<Money, MoneyError> {
+        if minor_units < 0 {
+            return Err(MoneyError::Negative(minor_units.to_string()));
+        }
+        Ok(Money { minor_units, currency })
+    }
+
+    pub fn zero(currency: Currency) -> Money {
+        Money { minor_units: 0, currency }
+    }
+
+    /// Parses a decimal amount such as `12.34` in the given currency. Signs,
+    /// exponents and digits beyond the currency's minor unit are rejected.
+    pub fn parse(amount: &str, currency: &str) -> Result<Money, MoneyError> {
+        let currency = Currency::new(currency)?;
+        if amount.starts_with('-') {
+            return Err(MoneyError::Negative(amount.to_string()));
+        }
+
+        let (units, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+        let digits = |s: &str| s.bytes().all(|byte| byte.is_ascii_digit());
+        if units.is_empty() || !digits(units) || !digits(fraction) || (amount.contains('.') && fraction.is_empty()) {
+            return Err(MoneyError::InvalidAmount(amount.to_string()));
+        }
+        if fraction.len() > currency.minor_unit_digits() as usize {
+            return Err(MoneyError::FractionalMinorUnit { amount: amount.to_string(), currency });
+        }
+
+        let scale = 10_i64.pow(currency.minor_unit_digits() - fraction.len() as u32);
+        let units: i64 = units.parse().map_err(|_| MoneyError::Overflow)?;
+        let fraction: i64 = if fraction.is_empty() { 0 } else { fraction.parse().map_err(|_| MoneyError::Overflow)? };
+        let minor_units = units.checked_mul(currency.minor_units_per_unit())
+            .and_then(|minor_units| minor_units.checked_add(fraction * scale))
+            .ok_or(MoneyError::Overflow)?;
+
+        Ok(Money { minor_units, currency })
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor_units == 0
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        let minor_units = self.minor_units.checked_add(other.minor_units).ok_or(MoneyError::Overflow)?;
+        Ok(Money { minor_units, currency: self.currency })
+    }
+
+    /// Fails with `Negative` if `other` is larger than this amount.
+    pub fn checked_sub(&self, other: &Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        let minor_units = self.minor_units.checked_sub(other.minor_units).ok_or(MoneyError::Overflow)?;
+        Money::new(minor_units, self.currency)
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch { expected: self.currency, actual: other.currency });
+        }
+        Ok(())
+    }
+
+    fn decimal(&self) -> String {
+        let digits = self.currency.minor_unit_digits() as usize;
+        let per_unit = self.currency.minor_units_per_unit();
+        if digits == 0 {
+            return self.minor_units.to_string();
+        }
+        format!("{}.{:0digits$}", self.minor_units / per_unit, self.minor_units % per_unit, digits = digits)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.decimal(), self.currency)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MoneyJson {
+    amount: String,
+    currency: String,
+}
+
+impl TryFrom<MoneyJson> for Money {
+    type Error = MoneyError;
+
+    fn try_from(json: MoneyJson) -> Result<Self, Self::Error> {
+        Money::parse(&json.amount, &json.currency)
+    }
+}
+
+impl From<Money> for MoneyJson {
+    fn from(money: Money) -> Self {
+        MoneyJson { amount: money.decimal(), currency: money.currency.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(amount: &str) -> Money {
+        Money::parse(amount, "USD").unwrap()
+    }
+
+    #[test]
+    fn parses_decimal_amounts_into_minor_units() {
+        assert_eq!(usd("12.34").minor_units(), 1234);
+        assert_eq!(usd("12.3").minor_units(), 1230);
+        assert_eq!(usd("12").minor_units(), 1200);
+        assert_eq!(Money::parse("1.234", "BHD").unwrap().minor_units(), 1234);
+    }
+
+    #[test]
+    fn rejects_fractions_of_the_minor_unit() {
+        assert!(matches!(Money::parse("1.005", "USD"), Err(MoneyError::FractionalMinorUnit { .. })));
+        assert!(matches!(Money::parse("1.5", "JPY"), Err(MoneyError::FractionalMinorUnit { .. })));
+    }
+
+    #[test]
+    fn jpy_has_no_decimal_places() {
+        let yen = Money::parse("1500", "JPY").unwrap();
+        assert_eq!(yen.minor_units(), 1500);
+        assert_eq!(yen.to_string(), "1500 JPY");
+    }
+
+    #[test]
+    fn rejects_negative_amounts() {
+        assert!(matches!(Money::parse("-1", "USD"), Err(MoneyError::Negative(_))));
+        assert!(matches!(Money::new(-1, Currency::new("USD").unwrap()), Err(MoneyError::Negative(_))));
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        for amount in ["+5", "1.", ".5", "", "1e3", "1,50", " 1", "1.2.3"] {
+            assert!(matches!(Money::parse(amount, "USD"), Err(MoneyError::InvalidAmount(_))), "{:?}", amount);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_currencies() {
+        for code in ["ZZZ", "usd", "US", "USDD", "XAU"] {
+            assert!(matches!(Currency::new(code), Err(MoneyError::InvalidCurrency(_))), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert_eq!(Money::parse("99999999999999999999", "USD"), Err(MoneyError::Overflow));
+        assert_eq!(Money::parse("92233720368547758.08", "USD"), Err(MoneyError::Overflow));
+
+        let max = Money::new(i64::MAX, Currency::new("USD").unwrap()).unwrap();
+        assert_eq!(max.checked_add(&usd("0.01")), Err(MoneyError::Overflow));
+    }
+
+    #[test]
+    fn arithmetic_requires_the_same_currency() {
+        let euros = Money::parse("1", "EUR").unwrap();
+        assert!(matches!(usd("1").checked_add(&euros), Err(MoneyError::CurrencyMismatch { .. })));
+        assert!(matches!(usd("1").checked_sub(&euros), Err(MoneyError::CurrencyMismatch { .. })));
+    }
+
+    #[test]
+    fn subtraction_below_zero_is_negative() {
+        assert_eq!(usd("5.10").checked_sub(&usd("5.10")), Ok(usd("0")));
+        assert!(matches!(usd("5.10").checked_sub(&usd("5.11")), Err(MoneyError::Negative(_))));
+    }
+
+    #[test]
+    fn json_round_trips_through_a_decimal_string() {
+        let json = serde_json::to_string(&usd("12.3")).unwrap();
+        assert_eq!(json, r#"{"amount":"12.30","currency":"USD"}"#);
+        assert_eq!(serde_json::from_str::<Money>(&json).unwrap(), usd("12.30"));
+    }
+
+    #[test]
+    fn json_rejects_numbers_and_invalid_amounts() {
+        for json in [
+            r#"{"amount":12.34,"currency":"USD"}"#,
+            r#"{"amount":"0.015","currency":"USD"}"#,
+            r#"{"amount":"-1","currency":"USD"}"#,
+            r#"{"amount":"1","currency":"ZZZ"}"#,
+            r#"{"amount":"1","currency":"USD","extra":true}"#,
+        ] {
+            assert!(serde_json::from_str::<Money>(json).is_err(), "{}", json);
+        }
+    }
+}
@@ -1,32 +1,65 @@
+use std::sync::Arc;
+use futures::TryStreamExt;
 use tracing::{info};
 
-use crate::messaging::events::Event;
-use crate::domain::account::Account;
-use crate::domain::events::Opened;
-use crate::db::MessageStore;
+use crate::messaging::{MessageData, TypedMessage};
+use crate::domain::account::{Account, AccountStatus};
+use crate::domain::events::{Closed, CloseRejected, Deposited, DepositRejected, OpenRejected, Opened, Withdrawn, WithdrawalRejected};
+use crate::db::{MessageStore, MessageStoreError};
+
+const BATCH_SIZE: i64 = 1000;
 
 #[derive(Clone)]
 pub struct AccountStore {
-    pub message_store: MessageStore,
+    pub message_store: Arc<dyn MessageStore>,
 }
 
 impl AccountStore {
 
-    pub async fn fetch(&self, account_id: &str) -> Result<(Account, Option<i64>), String> {
+    pub async fn fetch(&self, account_id: &str) -> Result<(Account, Option<i64>), MessageStoreError> {
         info!("Fetching account: {}", account_id);
-        let messages = self.message_store.get_stream_messages(&format!("account-{}", account_id), None, None, None).await.expect("Failed to fetch messages");
+        let stream_name = Account::stream_name(account_id);
+        let mut messages = self.message_store.read_stream(stream_name.as_str(), None, BATCH_SIZE);
 
         let mut account = Account::new(account_id);
         let mut position = None;
-        for message in messages {
+        while let Some(message) = messages.try_next().await? {
             info!("Processing account message: {:?}", message);
             let message_position = message.position;
             match message.message_type.as_str() {
-                "Opened" => {
-                    let event = Opened::from_message(message)?;
-                    account = self.apply_opened(account, event);
+                Opened::MESSAGE_TYPE => {
+                    let event = TypedMessage::<Opened>::from_message(message)?;
+                    account = self.apply_opened(account, event.into_data());
+                },
+                Deposited::MESSAGE_TYPE => {
+                    let event = TypedMessage::<Deposited>::from_message(message)?;
+                    account = self.apply_deposited(account, event.into_data())?;
+                },
+                DepositRejected::MESSAGE_TYPE => {
+                    let event = TypedMessage::<DepositRejected>::from_message(message)?;
+                    account.sequence = Some(event.sequence);
+                },
+                Withdrawn::MESSAGE_TYPE => {
+                    let event = TypedMessage::<Withdrawn>::from_message(message)?;
+                    account = self.apply_withdrawn(account, event.into_data())?;
+                },
+                WithdrawalRejected::MESSAGE_TYPE => {
+                    let event = TypedMessage::<WithdrawalRejected>::from_message(message)?;
+                    account.sequence = Some(event.sequence);
+                },
+                Closed::MESSAGE_TYPE => {
+                    let event = TypedMessage::<Closed>::from_message(message)?;
+                    account = self.apply_closed(account, event.into_data());
+                },
+                OpenRejected::MESSAGE_TYPE => {
+                    let event = TypedMessage::<OpenRejected>::from_message(message)?;
+                    account.sequence = Some(event.sequence);
                 },
-                _ => (),
+                CloseRejected::MESSAGE_TYPE => {
+                    let event = TypedMessage::<CloseRejected>::from_message(message)?;
+                    account.sequence = Some(event.sequence);
+                },
+                _ => {},
             }
             position = message_position;
         }
@@ -37,10 +70,31 @@ impl AccountStore {
     fn apply_opened(&self, account: Account, opened: Opened) -> Account {
         println!("Applying Opened event to account: {:?}", opened);
         Account {
-            id: account.id,
             opened_time: opened.processed_time,
-            balance: None,
-            status: None,
+            status: Some(AccountStatus::Open),
+            ..account
         }
     }
+
+    // The handler checked these before writing the events, so failing here
+    // means the stream itself is inconsistent
+    fn apply_deposited(&self, mut account: Account, deposited: Deposited) -> Result<Account, MessageStoreError> {
+        account.deposit(&deposited.amount)
+            .map_err(|e| MessageStoreError::Serialization(format!("Cannot apply Deposited {}: {}", deposited.sequence, e)))?;
+        account.sequence = Some(deposited.sequence);
+        Ok(account)
+    }
+
+    fn apply_withdrawn(&self, mut account: Account, withdrawn: Withdrawn) -> Result<Account, MessageStoreError> {
+        account.withdraw(&withdrawn.amount)
+            .map_err(|e| MessageStoreError::Serialization(format!("Cannot apply Withdrawn {}: {}", withdrawn.sequence, e)))?;
+        account.sequence = Some(withdrawn.sequence);
+        Ok(account)
+    }
+
+    fn apply_closed(&self, mut account: Account, closed: Closed) -> Account {
+        account.status = Some(AccountStatus::Closed);
+        account.sequence = Some(closed.sequence);
+        account
+    }
 }
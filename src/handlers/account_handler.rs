@@ -1,116 +1,303 @@
+use std::sync::Arc;
 use axum::async_trait;
-use serde::Serialize;
-use crate::messaging::Message;
-use crate::messaging::Handler;
-use crate::db::MessageStore;
+use crate::messaging::{Message, Metadata, StreamName, TypedMessage};
+use crate::messaging::{Dispatcher, Handler, HandlerError, Handles};
+use crate::db::{MessageStore, MessageStoreError};
 
 use tracing::info;
 
 use crate::messaging::events::Event;
-use crate::messaging::commands::Command;
+use crate::domain::account::Account;
+use crate::domain::money::MoneyError;
 use crate::domain::commands::{Open, Close, Deposit, Withdraw};
-use crate::domain::events::Opened;
+use crate::domain::events::{Closed, CloseRejected, Deposited, DepositRejected, OpenRejected, Opened, Withdrawn, WithdrawalRejected};
 use crate::domain::stores::AccountStore;
 use crate::util::Clock;
 
+/// Whether an account with money left in it can be closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClosePolicy {
+    /// Reject `Close` with `CloseRejected` unless the balance is zero.
+    #[default]
+    RequireZeroBalance,
+    /// Close the account whatever its balance.
+    AllowNonZeroBalance,
+}
+
 #[derive(Clone)]
 
 pub struct AccountHandler {
     clock: Clock,
     account_store: AccountStore,
-    message_store: MessageStore,
+    message_store: Arc<dyn MessageStore>,
+    dispatcher: Dispatcher<AccountHandler>,
+    close_policy: ClosePolicy,
 }
 
 impl AccountHandler {
-    pub fn new(message_store: MessageStore) -> AccountHandler {
+    pub fn new(message_store: Arc<dyn MessageStore>) -> AccountHandler {
         AccountHandler {
             clock: Clock {},
             message_store: message_store.clone(),
             account_store: AccountStore {
                 message_store,
             },
+            dispatcher: Dispatcher::new()
+                .handle::<Open>()
+                .handle::<Close>()
+                .handle::<Deposit>()
+                .handle::<Withdraw>(),
+            close_policy: ClosePolicy::default(),
         }
     }
+
+    pub fn with_close_policy(self, close_policy: ClosePolicy) -> Self {
+        AccountHandler { close_policy, ..self }
+    }
+
+    /// Message types this handler accepts, e.g. for logging at startup.
+    pub fn accepted_types(&self) -> Vec<&'static str> {
+        self.dispatcher.accepted_types()
+    }
 }
 
 #[async_trait]
 impl Handler for AccountHandler {
-    async fn handle(&self, message: Message) -> Result<(), String> {
+    async fn handle(&self, message: Message) -> Result<(), HandlerError> {
 
         info!("Handling message of type: {}", message.message_type);
-        match message.message_type.as_str() {
-            "Open" => {
-                let cmd = Open::from_message(message)?;
-                self.handle_open(cmd).await
-            },
-            "Close" => {
-                let cmd = Close::from_message(message)?;
-                self.handle_close(cmd).await
-            },
-            "Deposit" => {
-                let cmd = Deposit::from_message(message)?;
-                self.handle_deposit(cmd).await
-            },
-            "Withdraw" => {
-                let cmd = Withdraw::from_message(message)?;
-                self.handle_withdraw(cmd).await
-            },
-            _ => Err("Unsupported message type".to_string()),
-        }
+        self.dispatcher.dispatch(self, message).await
+    }
+}
+
+#[async_trait]
+impl Handles<Open> for AccountHandler {
+    async fn handle_message(&self, open: TypedMessage<Open>) -> Result<(), HandlerError> {
+        self.handle_open(open).await
+    }
+}
+
+#[async_trait]
+impl Handles<Close> for AccountHandler {
+    async fn handle_message(&self, close: TypedMessage<Close>) -> Result<(), HandlerError> {
+        self.handle_close(close).await
+    }
+}
+
+#[async_trait]
+impl Handles<Deposit> for AccountHandler {
+    async fn handle_message(&self, deposit: TypedMessage<Deposit>) -> Result<(), HandlerError> {
+        self.handle_deposit(deposit).await
+    }
+}
+
+#[async_trait]
+impl Handles<Withdraw> for AccountHandler {
+    async fn handle_message(&self, withdraw: TypedMessage<Withdraw>) -> Result<(), HandlerError> {
+        self.handle_withdraw(withdraw).await
     }
 }
 
 impl AccountHandler {
-    async fn handle_open(&self, open: Open) -> Result<(), String> {
+    async fn handle_open(&self, open: TypedMessage<Open>) -> Result<(), HandlerError> {
         println!("Handling Open for account: {}", open.account_id);
-        let account_id = open.account_id();
-        let (account, position) = self.account_store.fetch(account_id).await.expect("Failed to fetch account");
+        let account_id = open.account_id.as_str();
+        let sequence = sequence(&open)?;
+        let (account, position) = self.account_store.fetch(account_id).await?;
+        if account.processed(sequence) {
+            info!("Open {} already applied to account {} - skipping", sequence, account_id);
+            return Ok(());
+        }
+
+        if account.closed() {
+            let rejected = OpenRejected {
+                account_id: account_id.to_string(),
+                reason: "Account is closed".to_string(),
+                processed_time: Some(self.clock().now()),
+                sequence,
+            };
+            info!("Rejecting open: {:?}", rejected);
+            self.write(&Account::stream_name(account_id), &rejected, &Metadata::follow(&open.message), position).await?;
+            return Ok(());
+        }
+
         if account.opened() {
             info!("Account already opened: {} - proceeding", account_id);
             return Ok(());
         }
 
-        let processed_time = self.clock().now();
-        let opened = Opened::follow(&open);
         let opened = Opened {
-            processed_time: Some(processed_time),
-            ..opened
+            account_id: account_id.to_string(),
+            processed_time: Some(self.clock().now()),
         };
 
-        let stream_name = format!("account-{}", account_id);
+        let stream_name = Account::stream_name(account_id);
         info!("Generated Opened event: {:?}", opened);
 
-        self.write(&stream_name, opened, position).await.expect("Failed to write event");
+        self.write(&stream_name, &opened, &Metadata::follow(&open.message), position).await?;
 
         Ok(())
     }
 
-    async fn write(&self, stream_name: &str, event: impl Event + Serialize, position: Option<i64>) -> Result<(), String> {
+    /// Writes the event expecting the stream to still be at `position`, the
+    /// version the account was fetched at (`None` for a stream not yet written).
+    async fn write<E: Event>(&self, stream_name: &StreamName, event: &E, metadata: &Metadata, position: Option<i64>) -> Result<i64, MessageStoreError> {
         info!("Writing event to stream: {}", stream_name);
 
-        // derive the message type from the event type
-        let message_type = event.event_name();
-        let data = serde_json::to_value(&event).expect("Failed to serialize event").to_string();
-        self.message_store.write_message(stream_name, message_type, &data, None, position).await;
-
-        Ok(())
+        let message_type = E::MESSAGE_TYPE;
+        let data = serde_json::to_string(event)?;
+        let expected_version = position.unwrap_or(-1);
+        self.message_store.write_message(stream_name.as_str(), message_type, &data, Some(metadata), Some(expected_version)).await
     }
 
-    async fn handle_close(&self, close: Close) -> Result<(), String> {
-        println!("Handling Close for account: {}", close.account_id);
-        // Additional business logic for handling Close
+    async fn handle_close(&self, close: TypedMessage<Close>) -> Result<(), HandlerError> {
+        info!("Handling Close for account: {}", close.account_id);
+        let account_id = close.account_id.as_str();
+        let sequence = sequence(&close)?;
+        let (account, position) = self.account_store.fetch(account_id).await?;
+        if account.processed(sequence) {
+            info!("Close {} already applied to account {} - skipping", sequence, account_id);
+            return Ok(());
+        }
+        if account.closed() {
+            info!("Account already closed: {} - proceeding", account_id);
+            return Ok(());
+        }
+
+        let stream_name = Account::stream_name(account_id);
+        let metadata = Metadata::follow(&close.message);
+        let processed_time = Some(self.clock().now());
+
+        let rejection = if !account.opened() {
+            Some("Account is not open")
+        } else if account.has_balance() && self.close_policy == ClosePolicy::RequireZeroBalance {
+            Some("Account balance is not zero")
+        } else {
+            None
+        };
+
+        if let Some(reason) = rejection {
+            let rejected = CloseRejected {
+                account_id: account_id.to_string(),
+                balance: account.balance,
+                reason: reason.to_string(),
+                processed_time,
+                sequence,
+            };
+            info!("Rejecting close: {:?}", rejected);
+            self.write(&stream_name, &rejected, &metadata, position).await?;
+            return Ok(());
+        }
+
+        let closed = Closed {
+            account_id: account_id.to_string(),
+            processed_time,
+            sequence,
+        };
+        info!("Generated Closed event: {:?}", closed);
+        self.write(&stream_name, &closed, &metadata, position).await?;
+
         Ok(())
     }
 
-    async fn handle_deposit(&self, deposit: Deposit) -> Result<(), String> {
-        println!("Handling Deposit for account: {}", deposit.account_id);
-        // Additional business logic for handling Deposit
+    async fn handle_deposit(&self, deposit: TypedMessage<Deposit>) -> Result<(), HandlerError> {
+        info!("Handling Deposit for account: {}", deposit.account_id);
+        let account_id = deposit.account_id.as_str();
+        let sequence = sequence(&deposit)?;
+        let (account, position) = self.account_store.fetch(account_id).await?;
+        if account.processed(sequence) {
+            info!("Deposit {} already applied to account {} - skipping", sequence, account_id);
+            return Ok(());
+        }
+
+        let stream_name = Account::stream_name(account_id);
+        let metadata = Metadata::follow(&deposit.message);
+        let amount = deposit.amount;
+        let processed_time = Some(self.clock().now());
+
+        let rejection = if !account.opened() {
+            Some("Account is not open".to_string())
+        } else if account.closed() {
+            Some("Account is closed".to_string())
+        } else {
+            account.balance_after_deposit(&amount).err().map(|e| e.to_string())
+        };
+
+        if let Some(reason) = rejection {
+            let rejected = DepositRejected {
+                account_id: account_id.to_string(),
+                amount,
+                reason,
+                processed_time,
+                sequence,
+            };
+            info!("Rejecting deposit: {:?}", rejected);
+            self.write(&stream_name, &rejected, &metadata, position).await?;
+            return Ok(());
+        }
+
+        let deposited = Deposited {
+            account_id: account_id.to_string(),
+            amount,
+            processed_time,
+            sequence,
+        };
+        info!("Generated Deposited event: {:?}", deposited);
+        self.write(&stream_name, &deposited, &metadata, position).await?;
+
         Ok(())
     }
 
-    async fn handle_withdraw(&self, withdraw: Withdraw) -> Result<(), String> {
-        println!("Handling Withdraw for account: {}", withdraw.account_id);
-        // Additional business logic for handling Withdraw
+    async fn handle_withdraw(&self, withdraw: TypedMessage<Withdraw>) -> Result<(), HandlerError> {
+        info!("Handling Withdraw for account: {}", withdraw.account_id);
+        let account_id = withdraw.account_id.as_str();
+        let sequence = sequence(&withdraw)?;
+        let (account, position) = self.account_store.fetch(account_id).await?;
+        if account.processed(sequence) {
+            info!("Withdrawal {} already applied to account {} - skipping", sequence, account_id);
+            return Ok(());
+        }
+
+        let stream_name = Account::stream_name(account_id);
+        let metadata = Metadata::follow(&withdraw.message);
+        let amount = withdraw.amount;
+        let processed_time = Some(self.clock().now());
+
+        let rejection = if !account.opened() {
+            Some("Account is not open".to_string())
+        } else if account.closed() {
+            Some("Account is closed".to_string())
+        } else {
+            match account.balance_after_withdrawal(&amount) {
+                Ok(_) => None,
+                Err(MoneyError::Negative(_)) => Some("Insufficient funds".to_string()),
+                Err(e) => Some(e.to_string()),
+            }
+        };
+
+        if let Some(reason) = rejection {
+            let rejected = WithdrawalRejected {
+                account_id: account_id.to_string(),
+                amount,
+                balance: account.balance,
+                reason,
+                processed_time,
+                sequence,
+            };
+            info!("Rejecting withdrawal: {:?}", rejected);
+            self.write(&stream_name, &rejected, &metadata, position).await?;
+            return Ok(());
+        }
+
+        let withdrawn = Withdrawn {
+            account_id: account_id.to_string(),
+            amount,
+            processed_time,
+            sequence,
+        };
+        info!("Generated Withdrawn event: {:?}", withdrawn);
+        self.write(&stream_name, &withdrawn, &metadata, position).await?;
+
         Ok(())
     }
 
@@ -119,3 +306,325 @@ impl AccountHandler {
     }
 
 }
+
+/// The command's global position, recorded on the resulting event so that
+/// handling the command again can be recognised.
+fn sequence<C>(command: &TypedMessage<C>) -> Result<i64, HandlerError> {
+    command.global_position()
+        .ok_or_else(|| HandlerError::Permanent(format!("{} has no global position", command.message.message_type)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    use crate::db::{Condition, InMemoryMessageStore, NewMessage};
+    use crate::domain::money::Money;
+    use crate::messaging::MessageData;
+
+    const ACCOUNT_ID: &str = "123";
+
+    fn usd(amount: &str) -> Money {
+        Money::parse(amount, "USD").unwrap()
+    }
+
+    fn open() -> Open {
+        Open { account_id: ACCOUNT_ID.to_string() }
+    }
+
+    fn close() -> Close {
+        Close { account_id: ACCOUNT_ID.to_string() }
+    }
+
+    fn deposit(amount: &str) -> Deposit {
+        Deposit { account_id: ACCOUNT_ID.to_string(), amount: usd(amount) }
+    }
+
+    fn withdraw(amount: &str) -> Withdraw {
+        Withdraw { account_id: ACCOUNT_ID.to_string(), amount: usd(amount) }
+    }
+
+    /// Writes the command to the account's command stream and reads it back, as
+    /// the commands consumer would receive it.
+    async fn write_command<C: MessageData>(store: &InMemoryMessageStore, command: &C) -> Message {
+        let stream_name = Account::commands_stream_name(ACCOUNT_ID);
+        let data = serde_json::to_string(command).unwrap();
+        store.write_message(stream_name.as_str(), C::MESSAGE_TYPE, &data, None, None).await.unwrap();
+        store.get_last_message(stream_name.as_str()).await.unwrap().unwrap()
+    }
+
+    /// Writes the command and handles it, returning it so it can be redelivered.
+    async fn send<C: MessageData>(store: &InMemoryMessageStore, handler: &AccountHandler, command: &C) -> Message {
+        let message = write_command(store, command).await;
+        handler.handle(message.clone()).await.unwrap();
+        message
+    }
+
+    fn event_types(store: &InMemoryMessageStore) -> Vec<String> {
+        store.messages().into_iter()
+            .filter(|message| message.stream_name == Account::stream_name(ACCOUNT_ID).as_str())
+            .map(|message| message.message_type)
+            .collect()
+    }
+
+    async fn last_event<E: MessageData>(store: &InMemoryMessageStore) -> E {
+        let message = store.get_last_message(Account::stream_name(ACCOUNT_ID).as_str()).await.unwrap().unwrap();
+        TypedMessage::<E>::from_message(message).unwrap().into_data()
+    }
+
+    async fn balance(store: &Arc<InMemoryMessageStore>) -> Option<Money> {
+        let account_store = AccountStore { message_store: store.clone() };
+        account_store.fetch(ACCOUNT_ID).await.unwrap().0.balance
+    }
+
+    fn setup() -> (Arc<InMemoryMessageStore>, AccountHandler) {
+        let store = Arc::new(InMemoryMessageStore::new());
+        let handler = AccountHandler::new(store.clone());
+        (store, handler)
+    }
+
+    #[tokio::test]
+    async fn open_writes_opened_once() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &open()).await;
+
+        assert_eq!(event_types(&store), vec!["Opened"]);
+    }
+
+    #[tokio::test]
+    async fn accounts_opened_without_a_processed_time_are_open() {
+        let (store, handler) = setup();
+        let opened = Opened { account_id: ACCOUNT_ID.to_string(), processed_time: None };
+        let data = serde_json::to_string(&opened).unwrap();
+        store.write_message(Account::stream_name(ACCOUNT_ID).as_str(), Opened::MESSAGE_TYPE, &data, None, None).await.unwrap();
+
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &deposit("10")).await;
+
+        assert_eq!(event_types(&store), vec!["Opened", "Deposited"]);
+    }
+
+    #[tokio::test]
+    async fn deposits_and_withdrawals_change_the_balance() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &deposit("10")).await;
+        send(&store, &handler, &withdraw("3.50")).await;
+
+        assert_eq!(event_types(&store), vec!["Opened", "Deposited", "Withdrawn"]);
+        assert_eq!(balance(&store).await, Some(usd("6.50")));
+    }
+
+    #[tokio::test]
+    async fn withdrawing_more_than_the_balance_is_rejected() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &deposit("5")).await;
+        send(&store, &handler, &withdraw("5.01")).await;
+
+        let rejected = last_event::<WithdrawalRejected>(&store).await;
+        assert_eq!(rejected.reason, "Insufficient funds");
+        assert_eq!(rejected.balance, Some(usd("5")));
+        assert_eq!(balance(&store).await, Some(usd("5")));
+    }
+
+    #[tokio::test]
+    async fn withdrawing_before_any_deposit_is_rejected() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &withdraw("1")).await;
+
+        let rejected = last_event::<WithdrawalRejected>(&store).await;
+        assert_eq!(rejected.reason, "Insufficient funds");
+        assert_eq!(rejected.balance, None);
+    }
+
+    #[tokio::test]
+    async fn deposits_and_withdrawals_require_an_opened_account() {
+        let (store, handler) = setup();
+        send(&store, &handler, &deposit("10")).await;
+        assert_eq!(last_event::<DepositRejected>(&store).await.reason, "Account is not open");
+
+        send(&store, &handler, &withdraw("10")).await;
+        assert_eq!(last_event::<WithdrawalRejected>(&store).await.reason, "Account is not open");
+
+        assert_eq!(balance(&store).await, None);
+    }
+
+    #[tokio::test]
+    async fn closed_accounts_reject_deposits_withdrawals_and_reopening() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &close()).await;
+
+        send(&store, &handler, &deposit("10")).await;
+        assert_eq!(last_event::<DepositRejected>(&store).await.reason, "Account is closed");
+
+        send(&store, &handler, &withdraw("10")).await;
+        assert_eq!(last_event::<WithdrawalRejected>(&store).await.reason, "Account is closed");
+
+        send(&store, &handler, &open()).await;
+        assert_eq!(last_event::<OpenRejected>(&store).await.reason, "Account is closed");
+
+        assert_eq!(
+            event_types(&store),
+            vec!["Opened", "Closed", "DepositRejected", "WithdrawalRejected", "OpenRejected"]
+        );
+    }
+
+    #[tokio::test]
+    async fn deposits_in_another_currency_are_rejected() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &deposit("10")).await;
+        let euros = Deposit { account_id: ACCOUNT_ID.to_string(), amount: Money::parse("10", "EUR").unwrap() };
+        send(&store, &handler, &euros).await;
+
+        last_event::<DepositRejected>(&store).await;
+        assert_eq!(balance(&store).await, Some(usd("10")));
+    }
+
+    #[tokio::test]
+    async fn closing_requires_an_opened_account() {
+        let (store, handler) = setup();
+        send(&store, &handler, &close()).await;
+
+        assert_eq!(last_event::<CloseRejected>(&store).await.reason, "Account is not open");
+    }
+
+    #[tokio::test]
+    async fn closing_requires_a_zero_balance_by_default() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &deposit("1")).await;
+        send(&store, &handler, &close()).await;
+
+        let rejected = last_event::<CloseRejected>(&store).await;
+        assert_eq!(rejected.reason, "Account balance is not zero");
+        assert_eq!(rejected.balance, Some(usd("1")));
+
+        send(&store, &handler, &withdraw("1")).await;
+        send(&store, &handler, &close()).await;
+        assert_eq!(event_types(&store).last().unwrap(), "Closed");
+    }
+
+    #[tokio::test]
+    async fn close_policy_can_allow_closing_with_a_balance() {
+        let (store, handler) = setup();
+        let handler = handler.with_close_policy(ClosePolicy::AllowNonZeroBalance);
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &deposit("1")).await;
+        send(&store, &handler, &close()).await;
+
+        assert_eq!(event_types(&store), vec!["Opened", "Deposited", "Closed"]);
+    }
+
+    #[tokio::test]
+    async fn redelivered_commands_are_applied_once() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        let deposited = send(&store, &handler, &deposit("10")).await;
+        let withdrawn = send(&store, &handler, &withdraw("4")).await;
+        let rejected = send(&store, &handler, &withdraw("100")).await;
+
+        for message in [deposited, withdrawn, rejected] {
+            handler.handle(message).await.unwrap();
+        }
+
+        assert_eq!(event_types(&store), vec!["Opened", "Deposited", "Withdrawn", "WithdrawalRejected"]);
+        assert_eq!(balance(&store).await, Some(usd("6")));
+    }
+
+    #[tokio::test]
+    async fn commands_without_a_global_position_are_permanent_errors() {
+        let (store, handler) = setup();
+        let message = Message { global_position: None, ..write_command(&store, &deposit("1")).await };
+
+        assert!(matches!(handler.handle(message).await, Err(HandlerError::Permanent(_))));
+    }
+
+    /// Handles `concurrent` on another handler just before the first write, as
+    /// another instance working on the same account would.
+    struct ConcurrentStore {
+        store: Arc<InMemoryMessageStore>,
+        concurrent: Mutex<Option<Message>>,
+    }
+
+    #[async_trait]
+    impl MessageStore for ConcurrentStore {
+        async fn write_message(
+            &self,
+            stream_name: &str,
+            message_type: &str,
+            data: &str,
+            metadata: Option<&Metadata>,
+            expected_version: Option<i64>
+        ) -> Result<i64, MessageStoreError> {
+            let concurrent = self.concurrent.lock().unwrap().take();
+            if let Some(message) = concurrent {
+                AccountHandler::new(self.store.clone()).handle(message).await.unwrap();
+            }
+            self.store.write_message(stream_name, message_type, data, metadata, expected_version).await
+        }
+
+        async fn write_batch(&self, messages: &[NewMessage], expected_version: Option<i64>) -> Result<Vec<i64>, MessageStoreError> {
+            self.store.write_batch(messages, expected_version).await
+        }
+
+        async fn get_stream_messages(
+            &self,
+            stream_name: &str,
+            position: Option<i64>,
+            batch_size: Option<i64>,
+            condition: Option<&Condition>
+        ) -> Result<Vec<Message>, MessageStoreError> {
+            self.store.get_stream_messages(stream_name, position, batch_size, condition).await
+        }
+
+        async fn get_category_messages(
+            &self,
+            category_name: &str,
+            position: Option<i64>,
+            batch_size: Option<i64>,
+            correlation: Option<&str>,
+            consumer_group_member: Option<i64>,
+            consumer_group_size: Option<i64>,
+            condition: Option<&Condition>
+        ) -> Result<Vec<Message>, MessageStoreError> {
+            self.store.get_category_messages(
+                category_name, position, batch_size, correlation, consumer_group_member, consumer_group_size, condition
+            ).await
+        }
+
+        async fn get_last_message(&self, stream_name: &str) -> Result<Option<Message>, MessageStoreError> {
+            self.store.get_last_message(stream_name).await
+        }
+
+        async fn stream_version(&self, stream_name: &str) -> Result<Option<i64>, MessageStoreError> {
+            self.store.stream_version(stream_name).await
+        }
+    }
+
+    #[tokio::test]
+    async fn a_concurrent_write_is_retried_against_the_new_balance() {
+        let (store, handler) = setup();
+        send(&store, &handler, &open()).await;
+        send(&store, &handler, &deposit("10")).await;
+
+        let first = write_command(&store, &withdraw("7")).await;
+        let second = write_command(&store, &withdraw("5")).await;
+        let concurrent = ConcurrentStore { store: store.clone(), concurrent: Mutex::new(Some(first)) };
+        let handler = AccountHandler::new(Arc::new(concurrent));
+
+        // Decided on a balance of 10, but the first withdrawal landed in between
+        let error = handler.handle(second.clone()).await.unwrap_err();
+        assert!(matches!(error, HandlerError::Retryable(_)), "{:?}", error);
+        assert_eq!(event_types(&store), vec!["Opened", "Deposited", "Withdrawn"]);
+
+        handler.handle(second).await.unwrap();
+        assert_eq!(last_event::<WithdrawalRejected>(&store).await.balance, Some(usd("3")));
+        assert_eq!(balance(&store).await, Some(usd("3")));
+    }
+}
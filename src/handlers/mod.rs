@@ -1,3 +1,3 @@
 pub mod account_handler;
 
-pub use account_handler::AccountHandler;
\ No newline at end of file
+pub use account_handler::{AccountHandler, ClosePolicy};
\ No newline at end of file
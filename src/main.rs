@@ -1,16 +1,16 @@
 use dotenv::dotenv;
 use std::env;
-use tracing::Level;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
-use handlers::AccountHandler;
-use messaging::Consumer;
-
-mod db;
-mod domain;
-mod handlers;
-mod messaging;
-mod util;
+use account_demo::db;
+use account_demo::domain::account::Account;
+use account_demo::handlers::{AccountHandler, ClosePolicy};
+use account_demo::messaging;
+use account_demo::util::shutdown;
 
 #[tokio::main]
 async fn main() {
@@ -27,11 +27,60 @@ async fn main() {
     let db = db::Db::new(&database_url).await
         .expect("Failed to create database connection pool");
 
-    let message_store = db::MessageStore::new(db);
-    let handler = AccountHandler::new(message_store.clone());
-    let position_store = messaging::PositionStore::new(message_store.clone(), "account:commands".to_string(), None);
-    let account_consumer = messaging::CommandsConsumer::new(message_store, position_store, handler);
-    let _ = account_consumer.start("account:commands").await;
+    let postgres_store = db::PostgresMessageStore::new(db);
+    match postgres_store.notification_trigger_installed().await {
+        Ok(true) => {},
+        Ok(false) => warn!("Notification trigger not installed, consumers will poll; run install_notification_trigger"),
+        Err(e) => warn!("Failed to check for the notification trigger, consumers may poll: {}", e),
+    }
+    let message_store: Arc<dyn db::MessageStore> = Arc::new(postgres_store);
+
+    let mut settings = db::SubscriptionSettings::default();
+    if let Ok(interval) = env::var("POLL_INTERVAL_MS") {
+        let interval = interval.parse().expect("POLL_INTERVAL_MS must be a number of milliseconds");
+        settings.poll_interval = Duration::from_millis(interval);
+    }
+
+    let mut handler = AccountHandler::new(message_store.clone());
+    if env::var("ALLOW_CLOSE_WITH_BALANCE").is_ok_and(|allow| allow == "true") {
+        handler = handler.with_close_policy(ClosePolicy::AllowNonZeroBalance);
+    }
+    info!("Account handler accepts {}", handler.accepted_types().join(", "));
+    let commands_category = Account::commands_category();
+    let mut write_policy = messaging::PositionWritePolicy::default();
+    if let Ok(count) = env::var("POSITION_WRITE_EVERY") {
+        write_policy.every_messages = Some(count.parse().expect("POSITION_WRITE_EVERY must be a number of messages"));
+    }
+    if let Ok(interval) = env::var("POSITION_WRITE_INTERVAL_MS") {
+        let interval = interval.parse().expect("POSITION_WRITE_INTERVAL_MS must be a number of milliseconds");
+        write_policy.every_interval = Some(Duration::from_millis(interval));
+    }
+    let position_store = messaging::PositionStore::new(message_store.clone(), commands_category.to_string(), None)
+        .with_write_policy(write_policy);
+    let mut account_consumer = messaging::CommandsConsumer::new(message_store, position_store, handler)
+        .with_settings(settings);
+    if let Ok(workers) = env::var("CONSUMER_CONCURRENCY") {
+        account_consumer = account_consumer.with_concurrency(workers.parse().expect("CONSUMER_CONCURRENCY must be a number of workers"));
+    }
+
+    if let (Ok(member), Ok(size)) = (env::var("CONSUMER_GROUP_MEMBER"), env::var("CONSUMER_GROUP_SIZE")) {
+        let member = member.parse().expect("CONSUMER_GROUP_MEMBER must be a number");
+        let size = size.parse().expect("CONSUMER_GROUP_SIZE must be a number");
+        let group = db::ConsumerGroup::new(member, size).expect("Invalid consumer group");
+        account_consumer = account_consumer.with_consumer_group(group);
+    }
+
+    let mut host = messaging::ComponentHost::new();
+    host.register("account-commands", commands_category.as_str(), account_consumer);
+
+    let shutdown = CancellationToken::new();
+    shutdown::cancel_on_signal(shutdown.clone());
+
+    if let Err(e) = host.run(shutdown).await {
+        error!("Components stopped: {}", e);
+        std::process::exit(1);
+    }
+    info!("Components stopped cleanly");
 
 }
 
@@ -1,8 +1,4 @@
-use crate::messaging::Message;
+use crate::messaging::MessageData;
 
-pub trait Command {
-    fn from_message(message: Message) -> Result<Self, String> where Self: Sized;
-    fn account_id(&self) -> &str;
-    fn position(&self) -> Option<i64>;
-    fn message(&self) -> &Message;
-}
+/// Data of a command message; read it as a `TypedMessage<C>`.
+pub trait Command: MessageData {}
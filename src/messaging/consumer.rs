@@ -1,66 +1,243 @@
-use crate::db::{MessageStore};
-use crate::messaging::{Handler, Message, PositionStore};
+use crate::db::{ConsumerGroup, MessageStore, SubscriptionError, SubscriptionSettings};
+use crate::messaging::{stream_name, Handler, HandlerError, Message, PositionStore};
+use crate::messaging::error_policy::{dead_letter, ErrorPolicy, FailureAction};
+use crate::messaging::workers::WorkerPool;
 use axum::async_trait;
-use std::arch::global_asm;
+use std::fmt;
 use std::sync::Arc;
-use tracing::{info, error};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, error, warn};
+
+/// Why a consumer stopped before it was shut down.
+#[derive(Debug)]
+pub enum ConsumerError {
+    /// The starting position could not be read.
+    Position(String),
+    /// The subscription failed to read, was interrupted by an unavailable store, or
+    /// was halted by the error policy.
+    Subscription(SubscriptionError),
+}
+
+impl ConsumerError {
+    /// Whether the consumer stopped on purpose, on a message its error policy
+    /// says to halt on (`FailureAction::Halt`). Starting it again would only fail
+    /// on the same message. Any other failure, including a panicked worker, can be
+    /// restarted.
+    pub fn is_halted(&self) -> bool {
+        matches!(self, ConsumerError::Subscription(SubscriptionError::Halted(_)))
+    }
+}
+
+impl fmt::Display for ConsumerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsumerError::Position(e) => write!(f, "Failed to read starting position: {}", e),
+            ConsumerError::Subscription(e) => write!(f, "Subscription stopped: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConsumerError {}
 
 #[async_trait]
 pub trait Consumer {
-    async fn start(&self, stream_name: &str) -> Result<(), String>;
+    /// Consumes the stream until it fails or `shutdown` is cancelled. On the way
+    /// out the message in flight is finished and the position is written.
+    async fn start(&self, stream_name: &str, shutdown: CancellationToken) -> Result<(), ConsumerError>;
 }
 
 #[async_trait]
 pub trait StreamConsumer<T: Handler + Send + Sync + Clone + 'static>: Consumer {
-    async fn process_message(&self, handler: Arc<T>, message: Message) -> Result<(), String>;
+    async fn process_message(&self, handler: Arc<T>, message: Message) -> Result<(), HandlerError>;
 }
 
+/// Subscribes to a category, dispatches each message to the handler, and tracks
+/// its progress in its own position stream.
 #[derive(Clone)]
-pub struct CommandsConsumer<T: Handler + Send + Sync + Clone + 'static> {
-    store: MessageStore,
+pub struct CategoryConsumer<T: Handler + Send + Sync + Clone + 'static> {
+    store: Arc<dyn MessageStore>,
     position_store: PositionStore,
     handler: T,
+    settings: SubscriptionSettings,
+    error_policy: ErrorPolicy,
+    concurrency: usize,
 }
 
-impl<T: Handler + Send + Sync + Clone + 'static> CommandsConsumer<T> {
-    pub fn new(store: MessageStore, position_store: PositionStore, handler: T) -> Self {
-        CommandsConsumer { store, position_store, handler }
+/// Consumes a command category such as `account:commands`.
+pub type CommandsConsumer<T> = CategoryConsumer<T>;
+
+/// Consumes an entity's event category such as `account`, e.g. for projections,
+/// notifications and process managers.
+pub type EventsConsumer<T> = CategoryConsumer<T>;
+
+impl<T: Handler + Send + Sync + Clone + 'static> CategoryConsumer<T> {
+    pub fn new(store: Arc<dyn MessageStore>, position_store: PositionStore, handler: T) -> Self {
+        CategoryConsumer {
+            store,
+            position_store,
+            handler,
+            settings: SubscriptionSettings::default(),
+            error_policy: ErrorPolicy::default(),
+            concurrency: 1,
+        }
+    }
+
+    /// Consumer for `category` whose position is kept in `{category}:position-{identifier}`.
+    /// Several consumers of the same category, e.g. different projections, each need
+    /// their own identifier.
+    pub fn for_category(store: Arc<dyn MessageStore>, category: &str, identifier: &str, handler: T) -> Self {
+        let position_store = PositionStore::new(store.clone(), category.to_string(), Some(identifier.to_string()));
+        CategoryConsumer::new(store, position_store, handler)
+    }
+
+    pub fn with_error_policy(self, error_policy: ErrorPolicy) -> Self {
+        CategoryConsumer { error_policy, ..self }
+    }
+
+    pub fn with_settings(self, settings: SubscriptionSettings) -> Self {
+        CategoryConsumer { settings, ..self }
+    }
+
+    /// Consumes only this member's slice of the category. The position store is
+    /// switched to the member's own position stream, compounding the member onto
+    /// any existing identifier (`account:position-projection+1`).
+    pub fn with_consumer_group(self, group: ConsumerGroup) -> Self {
+        let member = group.identifier();
+        let identifier = match &self.position_store.identifier {
+            Some(identifier) => stream_name::compound_id(&[identifier, &member]),
+            None => member,
+        };
+        let position_store = self.position_store.clone().with_identifier(Some(identifier));
+        let settings = SubscriptionSettings { consumer_group: Some(group), ..self.settings };
+        CategoryConsumer { position_store, settings, ..self }
+    }
+
+    /// Handles messages for up to `workers` entities at once. Messages of the same
+    /// entity are still handled one at a time, in order, and the recorded position
+    /// never passes a message that has not finished. With 1, the default, every
+    /// message is handled in turn on the subscription itself.
+    pub fn with_concurrency(self, workers: usize) -> Self {
+        CategoryConsumer { concurrency: workers.max(1), ..self }
+    }
+
+    /// Consumes only messages correlated to the given category, e.g. replies
+    /// to commands this component wrote with `correlationStreamName` set.
+    pub fn with_correlation(self, correlation_category: &str) -> Self {
+        let settings = SubscriptionSettings { correlation: Some(correlation_category.to_string()), ..self.settings };
+        CategoryConsumer { settings, ..self }
     }
 }
 
 #[async_trait]
-impl<T: Handler + Send + Sync + Clone + 'static> Consumer for CommandsConsumer<T> {
-    async fn start(&self, stream_name: &str) -> Result<(), String> {
+impl<T: Handler + Send + Sync + Clone + 'static> Consumer for CategoryConsumer<T> {
+    async fn start(&self, stream_name: &str, shutdown: CancellationToken) -> Result<(), ConsumerError> {
         let handler = Arc::new(self.handler.clone());
         let position_store = self.position_store.clone();
+        let store = self.store.clone();
+        let error_policy = self.error_policy.clone();
+        let dead_letter_stream = error_policy.dead_letter_stream_name(stream_name);
+        let process = move |message: Message| {
+            process_message(handler.clone(), store.clone(), error_policy.clone(), dead_letter_stream.clone(), message)
+        };
+
+        let starting_position = position_store.get().await.map_err(ConsumerError::Position)?;
+
+        let subscription = async move {
+            if self.concurrency > 1 {
+                // Halting one worker stops the subscription without cancelling the caller's token
+                let stop = shutdown.child_token();
+                let pool = Arc::new(WorkerPool::start(self.concurrency, position_store, stop.clone(), process));
+                let dispatcher = pool.clone();
+                let result = self.store.subscribe_to_stream(stream_name, starting_position, &self.settings, &stop, move |message| {
+                    let pool = dispatcher.clone();
+                    Box::pin(async move {
+                        pool.dispatch(message).await;
+                        Ok(())
+                    })
+                }).await;
 
-        let starting_position = position_store.get().await;
-
-        // Assuming store.subscribe_to_stream now only requires what it absolutely needs.
-        self.store.subscribe_to_stream(stream_name, starting_position, move |message| {
-            let handler_clone = handler.clone();
-            let position_store_clone = position_store.clone();
-            let global_position = message.global_position.unwrap();
-            Box::pin(async move {
-                match handler_clone.handle(message).await {
-                    Ok(_) => {
-                        info!("Message processed successfully.");
-                        if let Err(e) = position_store_clone.update_position(global_position).await {
+                let pool = Arc::into_inner(pool).expect("the subscription has dropped its dispatcher");
+                match pool.shutdown().await {
+                    Some(e) => Err(e),
+                    None => result,
+                }
+            } else {
+                self.store.subscribe_to_stream(stream_name, starting_position, &self.settings, &shutdown, move |message| {
+                    let position_store = position_store.clone();
+                    let process = process.clone();
+                    let global_position = message.global_position.unwrap();
+                    Box::pin(async move {
+                        process(message).await?;
+                        if let Err(e) = position_store.update_position(global_position).await {
                             error!("Failed to update position: {}", e);
                         }
-                    },
-                    Err(e) => error!("Failed to process message: {}", e),
-                }
-            })
-        }).await;
+                        Ok(())
+                    })
+                }).await
+            }
+        };
+        // The interval write policy is otherwise only checked as messages arrive
+        let result = tokio::select! {
+            result = subscription => result,
+            _ = self.position_store.flush_when_due() => unreachable!("flush_when_due never returns"),
+        };
+
+        if let Err(e) = self.position_store.flush().await {
+            error!("Failed to write position on stop: {}", e);
+        }
+
+        result.map_err(ConsumerError::Subscription)
+    }
+}
 
-        Ok(())
+/// Handles one message, retrying it as the error policy allows and then
+/// dead-lettering it. Returns `Err` when the consumer should stop instead: halted
+/// when the error policy says so, or interrupted when the store is unavailable,
+/// in which case the message is handled again once the consumer is restarted.
+async fn process_message<T: Handler + Send + Sync>(
+    handler: Arc<T>,
+    store: Arc<dyn MessageStore>,
+    error_policy: ErrorPolicy,
+    dead_letter_stream: String,
+    message: Message,
+) -> Result<(), SubscriptionError> {
+    let mut attempts = 0;
+    let error = loop {
+        attempts += 1;
+        match handler.handle(message.clone()).await {
+            Ok(_) => {
+                info!("Message processed successfully.");
+                break None;
+            },
+            Err(e) if e.is_retryable() && !error_policy.retry.exhausted(attempts) => {
+                let backoff = error_policy.retry.backoff(attempts);
+                warn!("Failed to process message (attempt {}), retrying in {:?}: {}", attempts, backoff, e);
+                tokio::time::sleep(backoff).await;
+            },
+            Err(e) => break Some(e),
+        }
+    };
+
+    if let Some(e) = error {
+        error!("Failed to process message after {} attempts: {}", attempts, e);
+        if e.is_unavailable() {
+            return Err(SubscriptionError::Interrupted(e.to_string()));
+        }
+        match error_policy.on_failure {
+            FailureAction::DeadLetter => {
+                dead_letter(&store, &dead_letter_stream, &message, &e, attempts).await
+                    .map_err(|e| SubscriptionError::Interrupted(format!("Failed to dead-letter message: {}", e)))?;
+            },
+            FailureAction::Halt => return Err(SubscriptionError::Halted(e.to_string())),
+        }
     }
+
+    Ok(())
 }
 
 #[async_trait]
-impl<T: Handler + Send + Sync + Clone + 'static> StreamConsumer<T> for CommandsConsumer<T> {
-    async fn process_message(&self, handler: Arc<T>, message: Message) -> Result<(), String> {
+impl<T: Handler + Send + Sync + Clone + 'static> StreamConsumer<T> for CategoryConsumer<T> {
+    async fn process_message(&self, handler: Arc<T>, message: Message) -> Result<(), HandlerError> {
         handler.handle(message).await
     }
 }
@@ -0,0 +1,106 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use axum::async_trait;
+use tracing::debug;
+
+use crate::messaging::{HandlerError, Message, MessageData, TypedMessage};
+
+/// Handling of one kind of message, a command or an event, registered with
+/// `Dispatcher::handle::<C>()`.
+#[async_trait]
+pub trait Handles<C: MessageData>: Send + Sync {
+    async fn handle_message(&self, message: TypedMessage<C>) -> Result<(), HandlerError>;
+}
+
+/// What the dispatcher does with a message type no handler was registered for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownMessagePolicy {
+    /// Skip the message, e.g. in a consumer only interested in some of a category's events.
+    Ignore,
+    /// Fail it with a permanent error, so it is dead-lettered or halts the consumer.
+    Error,
+}
+
+type Route<H> = for<'a> fn(&'a H, Message) -> Pin<Box<dyn Future<Output = Result<(), HandlerError>> + Send + 'a>>;
+
+/// Routes messages to a handler's `Handles<C>` implementations by message type.
+///
+/// ```ignore
+/// let dispatcher = Dispatcher::new()
+///     .handle::<Open>()
+///     .handle::<Deposit>();
+/// dispatcher.dispatch(&handler, message).await?;
+/// ```
+pub struct Dispatcher<H> {
+    routes: Vec<(&'static str, Route<H>)>,
+    unknown: UnknownMessagePolicy,
+}
+
+impl<H> Clone for Dispatcher<H> {
+    fn clone(&self) -> Self {
+        Dispatcher { routes: self.routes.clone(), unknown: self.unknown }
+    }
+}
+
+impl<H: Send + Sync + 'static> Default for Dispatcher<H> {
+    fn default() -> Self {
+        Dispatcher::new()
+    }
+}
+
+impl<H: Send + Sync + 'static> Dispatcher<H> {
+    pub fn new() -> Self {
+        Dispatcher {
+            routes: Vec::new(),
+            unknown: UnknownMessagePolicy::Error,
+        }
+    }
+
+    /// Routes messages whose type is `C::MESSAGE_TYPE` to `H`'s `Handles<C>`.
+    pub fn handle<C>(mut self) -> Self
+    where
+        C: MessageData,
+        H: Handles<C>,
+    {
+        let route: Route<H> = |handler, message| Box::pin(async move {
+            let message = TypedMessage::<C>::from_message(message)?;
+            handler.handle_message(message).await
+        });
+        self.routes.retain(|(message_type, _)| *message_type != C::MESSAGE_TYPE);
+        self.routes.push((C::MESSAGE_TYPE, route));
+        self
+    }
+
+    pub fn with_unknown_message_policy(self, unknown: UnknownMessagePolicy) -> Self {
+        Dispatcher { unknown, ..self }
+    }
+
+    /// Message types with a registered handler, in the order they were registered.
+    pub fn accepted_types(&self) -> Vec<&'static str> {
+        self.routes.iter().map(|(message_type, _)| *message_type).collect()
+    }
+
+    pub fn accepts(&self, message_type: &str) -> bool {
+        self.routes.iter().any(|(accepted, _)| *accepted == message_type)
+    }
+
+    pub async fn dispatch(&self, handler: &H, message: Message) -> Result<(), HandlerError> {
+        let route = self.routes.iter()
+            .find(|(message_type, _)| *message_type == message.message_type)
+            .map(|(_, route)| *route);
+
+        match (route, self.unknown) {
+            (Some(route), _) => route(handler, message).await,
+            (None, UnknownMessagePolicy::Ignore) => {
+                debug!("Ignoring message of type {}", message.message_type);
+                Ok(())
+            },
+            (None, UnknownMessagePolicy::Error) => Err(HandlerError::Permanent(format!(
+                "Unsupported message type: {} (accepts {})",
+                message.message_type,
+                self.accepted_types().join(", "),
+            ))),
+        }
+    }
+}
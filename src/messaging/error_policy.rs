@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::Value;
+use tracing::{error, info};
+
+use crate::db::{MessageStore, MessageStoreError};
+use crate::messaging::{HandlerError, Message, Metadata, StreamName};
+use crate::util::RetryPolicy;
+
+pub const DEAD_LETTER_TYPE: &str = "dlq";
+
+/// What a consumer does with a message once its handler has failed for good.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureAction {
+    /// Park the message in a dead-letter stream and move on to the next one.
+    DeadLetter,
+    /// Stop the consumer without advancing its position.
+    Halt,
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorPolicy {
+    /// Retries for `HandlerError::Retryable` and `HandlerError::Unavailable`. Once exhausted a
+    /// retryable failure is treated as permanent, while an unavailable store interrupts the
+    /// consumer so the host restarts it and the message is handled again.
+    pub retry: RetryPolicy,
+    pub on_failure: FailureAction,
+    /// Defaults to the consumed category with the `dlq` type added, e.g. `account:commands+dlq`.
+    /// Adding a type, rather than an id as in `account:commands-dlq`, keeps dead letters
+    /// out of the category the consumer reads from.
+    pub dead_letter_stream: Option<String>,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy {
+            retry: RetryPolicy { max_failures: Some(3), ..RetryPolicy::default() },
+            on_failure: FailureAction::DeadLetter,
+            dead_letter_stream: None,
+        }
+    }
+}
+
+impl ErrorPolicy {
+    pub fn dead_letter_stream_name(&self, category: &str) -> String {
+        match &self.dead_letter_stream {
+            Some(stream_name) => stream_name.clone(),
+            None => StreamName::new(category).add_type(DEAD_LETTER_TYPE).into(),
+        }
+    }
+}
+
+/// Writes the failed message, unchanged, to the dead-letter stream. The metadata's
+/// causation fields point at the original message, and `failureReason` and
+/// `failedAttempts` properties record why it was parked.
+pub async fn dead_letter(
+    store: &Arc<dyn MessageStore>,
+    dead_letter_stream: &str,
+    message: &Message,
+    error: &HandlerError,
+    attempts: u32,
+) -> Result<i64, MessageStoreError> {
+    let mut properties = HashMap::new();
+    properties.insert("failureReason".to_string(), Value::from(error.reason()));
+    properties.insert("failedAttempts".to_string(), Value::from(attempts));
+
+    let metadata = Metadata {
+        properties,
+        ..Metadata::follow(message)
+    };
+
+    match store.write_message(dead_letter_stream, &message.message_type, &message.data, Some(&metadata), None).await {
+        Ok(position) => {
+            info!("Dead-lettered message {} from {} to {}", message.id, message.stream_name, dead_letter_stream);
+            Ok(position)
+        },
+        Err(e) => {
+            error!("Failed to dead-letter message {} to {}: {}", message.id, dead_letter_stream, e);
+            Err(e)
+        }
+    }
+}
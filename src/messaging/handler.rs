@@ -1,9 +1,69 @@
+use std::fmt;
+
 use axum::async_trait;
 
+use crate::db::MessageStoreError;
 use crate::messaging::Message;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandlerError {
+    /// The message may succeed if handled again, e.g. after a concurrency conflict.
+    Retryable(String),
+    /// The message store could not be reached, e.g. while the database is failing
+    /// over. Retried like `Retryable`, but the message is never dead-lettered for it.
+    Unavailable(String),
+    /// Handling the message again will fail the same way, e.g. it cannot be parsed.
+    Permanent(String),
+}
+
+impl HandlerError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, HandlerError::Retryable(_) | HandlerError::Unavailable(_))
+    }
+
+    pub fn is_unavailable(&self) -> bool {
+        matches!(self, HandlerError::Unavailable(_))
+    }
+
+    pub fn reason(&self) -> &str {
+        match self {
+            HandlerError::Retryable(reason) | HandlerError::Unavailable(reason) | HandlerError::Permanent(reason) => reason,
+        }
+    }
+}
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandlerError::Retryable(reason) => write!(f, "Retryable handler error: {}", reason),
+            HandlerError::Unavailable(reason) => write!(f, "Message store unavailable: {}", reason),
+            HandlerError::Permanent(reason) => write!(f, "Permanent handler error: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for HandlerError {}
+
+impl From<String> for HandlerError {
+    fn from(reason: String) -> Self {
+        HandlerError::Permanent(reason)
+    }
+}
+
+impl From<MessageStoreError> for HandlerError {
+    fn from(e: MessageStoreError) -> Self {
+        if e.is_transient() {
+            HandlerError::Unavailable(e.to_string())
+        } else if e.is_expected_version() {
+            HandlerError::Retryable(e.to_string())
+        } else {
+            HandlerError::Permanent(e.to_string())
+        }
+    }
+}
+
 #[async_trait]
 pub trait Handler: Send + Sync {
-    async fn handle(&self, message: Message) -> Result<(), String>;
+    async fn handle(&self, message: Message) -> Result<(), HandlerError>;
 
 }
@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use futures::future::join_all;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::messaging::Consumer;
+use crate::util::RetryPolicy;
+
+struct Component {
+    name: String,
+    stream_name: String,
+    consumer: Arc<dyn Consumer + Send + Sync>,
+}
+
+/// Runs many consumers in one process. Each runs in its own tokio task and is
+/// restarted with backoff when it fails or panics; they all stop together on
+/// shutdown, or when one fails more often than the restart policy allows. A
+/// consumer halted by its error policy (`FailureAction::Halt`) is not restarted,
+/// and the others keep running.
+pub struct ComponentHost {
+    components: Vec<Component>,
+    restart_policy: RetryPolicy,
+}
+
+impl Default for ComponentHost {
+    fn default() -> Self {
+        ComponentHost::new()
+    }
+}
+
+impl ComponentHost {
+    pub fn new() -> Self {
+        ComponentHost {
+            components: Vec::new(),
+            restart_policy: RetryPolicy { max_failures: None, ..RetryPolicy::default() },
+        }
+    }
+
+    pub fn with_restart_policy(self, restart_policy: RetryPolicy) -> Self {
+        ComponentHost { restart_policy, ..self }
+    }
+
+    pub fn register(&mut self, name: &str, stream_name: &str, consumer: impl Consumer + Send + Sync + 'static) {
+        info!("Registering component {} on {}", name, stream_name);
+        self.components.push(Component {
+            name: name.to_string(),
+            stream_name: stream_name.to_string(),
+            consumer: Arc::new(consumer),
+        });
+    }
+
+    pub fn component_names(&self) -> Vec<&str> {
+        self.components.iter().map(|component| component.name.as_str()).collect()
+    }
+
+    /// Runs every component until `shutdown` is cancelled, or one gives up
+    /// restarting and stops the rest. Returns the first error of a component that
+    /// halted or gave up restarting.
+    pub async fn run(self, shutdown: CancellationToken) -> Result<(), String> {
+        info!("Starting {} components", self.components.len());
+
+        let supervisors = self.components.into_iter().map(|component| {
+            let shutdown = shutdown.clone();
+            let restart_policy = self.restart_policy.clone();
+            tokio::spawn(supervise(component, restart_policy, shutdown))
+        });
+
+        let mut first_error = None;
+        for result in join_all(supervisors).await {
+            let result = result.unwrap_or_else(|e| Err(format!("Supervisor task failed: {}", e)));
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+
+        info!("All components stopped");
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+async fn supervise(component: Component, restart_policy: RetryPolicy, shutdown: CancellationToken) -> Result<(), String> {
+    let mut failures = 0;
+    loop {
+        let consumer = component.consumer.clone();
+        let stream_name = component.stream_name.clone();
+        let consumer_shutdown = shutdown.clone();
+
+        info!("Starting component {}", component.name);
+        let started_at = Instant::now();
+        // A separate task so a panicking consumer is caught here rather than taking the host down
+        let result = tokio::spawn(async move { consumer.start(&stream_name, consumer_shutdown).await }).await;
+
+        if shutdown.is_cancelled() {
+            info!("Component {} stopped", component.name);
+            return Ok(());
+        }
+
+        let reason = match result {
+            Ok(Ok(())) => "stopped unexpectedly".to_string(),
+            Ok(Err(e)) if e.is_halted() => {
+                // Restarting would only retry the message it halted on
+                error!("Component {} halted, not restarting: {}", component.name, e);
+                return Err(format!("Component {} halted: {}", component.name, e));
+            },
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => format!("panicked: {}", e),
+        };
+
+        // A component that ran for a while before failing starts its backoff over
+        if started_at.elapsed() > restart_policy.max_backoff {
+            failures = 0;
+        }
+        failures += 1;
+        if restart_policy.exhausted(failures) {
+            error!("Component {} failed {} times, stopping all components: {}", component.name, failures, reason);
+            shutdown.cancel();
+            return Err(format!("Component {} failed: {}", component.name, reason));
+        }
+
+        let backoff = restart_policy.backoff(failures);
+        warn!("Component {} failed (attempt {}), restarting in {:?}: {}", component.name, failures, backoff, reason);
+        tokio::select! {
+            _ = shutdown.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(backoff) => {},
+        }
+    }
+}
@@ -1,29 +1,48 @@
 // src/messaging/message.rs
 
-use std::default;
-
 use sqlx::FromRow;
 use chrono::NaiveDateTime;
 
+use crate::messaging::Metadata;
+use crate::messaging::stream_name;
+
 #[derive(Debug, FromRow, Clone)]
 pub struct Message {
+    pub id: String,
+    pub stream_name: String,
     pub global_position: Option<i64>,
     pub position: Option<i64>,
     pub message_type: String,
     pub data: String,
-    pub metadata: Option<String>,
+    #[sqlx(try_from = "Option<String>")]
+    pub metadata: Metadata,
     pub time: NaiveDateTime,
 }
 
-impl Message {
-    pub fn default() -> Self {
+impl Default for Message {
+    fn default() -> Self {
         Message {
+            id: "".to_string(),
+            stream_name: "".to_string(),
             global_position: None,
             position: None,
             message_type: "".to_string(),
             data: "".to_string(),
-            metadata: None,
-            time: NaiveDateTime::from_timestamp(0, 0),
+            metadata: Metadata::default(),
+            time: NaiveDateTime::UNIX_EPOCH,
         }
     }
-}
\ No newline at end of file
+}
+
+impl Message {
+    /// The part of the stream name before the first `-`, e.g. `account` for `account-123`.
+    pub fn category(&self) -> &str {
+        stream_name::category(&self.stream_name)
+    }
+
+    /// The part of the stream name after the first `-`, e.g. `123` for `account-123`.
+    /// Category streams such as `account:commands` have no entity id.
+    pub fn entity_id(&self) -> Option<&str> {
+        stream_name::id(&self.stream_name)
+    }
+}
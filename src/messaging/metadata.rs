@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::messaging::Message;
+
+/// Message metadata, stored in Message DB's `metadata` column using the same
+/// camelCase field names as the other Message DB client libraries.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub causation_message_stream_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub causation_message_position: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub causation_message_global_position: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_stream_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_stream_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, Value>,
+}
+
+impl Metadata {
+    /// Metadata for a message written in response to `source`: causation points at
+    /// the source message, and correlation, reply stream and trace id carry over.
+    pub fn follow(source: &Message) -> Metadata {
+        let metadata = &source.metadata;
+        Metadata {
+            causation_message_stream_name: Some(source.stream_name.clone()),
+            causation_message_position: source.position,
+            causation_message_global_position: source.global_position,
+            correlation_stream_name: metadata.correlation_stream_name.clone(),
+            reply_stream_name: metadata.reply_stream_name.clone(),
+            schema_version: None,
+            trace_id: metadata.trace_id.clone(),
+            properties: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Metadata::default()
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+impl TryFrom<Option<String>> for Metadata {
+    type Error = serde_json::Error;
+
+    fn try_from(metadata: Option<String>) -> Result<Self, Self::Error> {
+        match metadata.as_deref() {
+            None | Some("null") | Some("") => Ok(Metadata::default()),
+            Some(json) => serde_json::from_str(json),
+        }
+    }
+}
@@ -1,12 +1,26 @@
 pub mod consumer;
 pub mod message;
+pub mod typed_message;
+pub mod metadata;
 pub mod events;
 pub mod commands;
 pub mod handler;
+pub mod error_policy;
 pub mod position_store;
+pub mod stream_name;
+pub mod host;
+pub mod dispatcher;
+mod workers;
 
-pub use consumer::{Consumer, CommandsConsumer};
+pub use consumer::{Consumer, ConsumerError, CategoryConsumer, CommandsConsumer, EventsConsumer};
 pub use message::Message;
-pub use handler::Handler;
-pub use position_store::PositionStore;
-pub use events::Event;
\ No newline at end of file
+pub use typed_message::{DecodeError, MessageData, TypedMessage};
+pub use metadata::Metadata;
+pub use handler::{Handler, HandlerError};
+pub use error_policy::{ErrorPolicy, FailureAction};
+pub use position_store::{PositionStore, PositionWritePolicy};
+pub use stream_name::StreamName;
+pub use host::ComponentHost;
+pub use dispatcher::{Dispatcher, Handles, UnknownMessagePolicy};
+pub use events::Event;
+pub use commands::Command;
\ No newline at end of file
@@ -1,50 +1,131 @@
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
-use tracing::{info, debug};
+use tokio::time::Instant;
+use tracing::{info, debug, error};
 
-use crate::db::MessageStore;
+use crate::db::{ConsumerGroup, MessageStore};
 use crate::util::Clock;
-use crate::messaging::Message;
-use crate::messaging::events::{Event, Recorded};
+use crate::messaging::{MessageData, StreamName, TypedMessage};
+use crate::messaging::events::Recorded;
+
+const POSITION_TYPE: &str = "position";
+
+/// When `update_position` writes a `Recorded` event. With both limits set,
+/// whichever is reached first triggers the write. Both are checked as messages
+/// are processed, so an idle consumer's position is written by `flush`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PositionWritePolicy {
+    /// Write after this many messages have been processed since the last write.
+    pub every_messages: Option<u64>,
+    /// Write once this long has passed since the last write.
+    pub every_interval: Option<Duration>,
+}
+
+impl Default for PositionWritePolicy {
+    fn default() -> Self {
+        PositionWritePolicy {
+            every_messages: Some(5),
+            every_interval: None,
+        }
+    }
+}
+
+impl PositionWritePolicy {
+    pub fn every_messages(count: u64) -> Self {
+        PositionWritePolicy { every_messages: Some(count), every_interval: None }
+    }
+
+    pub fn every_interval(interval: Duration) -> Self {
+        PositionWritePolicy { every_messages: None, every_interval: Some(interval) }
+    }
+
+    fn due(&self, unsaved: u64, since_saved: Duration) -> bool {
+        let by_count = self.every_messages.is_some_and(|count| unsaved >= count.max(1));
+        let by_time = self.every_interval.is_some_and(|interval| since_saved >= interval);
+        by_count || by_time
+    }
+}
+
+struct SaveState {
+    saved_position: i64,
+    unsaved: u64,
+    saved_at: Instant,
+}
 
 #[derive(Clone)]
 pub struct PositionStore {
-    pub message_store: MessageStore,
+    pub message_store: Arc<dyn MessageStore>,
     pub category: String,
     pub identifier: Option<String>,
     pub position: Arc<Mutex<i64>>,
+    write_policy: PositionWritePolicy,
+    save_state: Arc<Mutex<SaveState>>,
     clock: Clock,
 }
 
 impl PositionStore {
-    pub fn new(message_store: MessageStore, category: String, identifier: Option<String>) -> Self {
+    pub fn new(message_store: Arc<dyn MessageStore>, category: String, identifier: Option<String>) -> Self {
         PositionStore {
             message_store,
             category,
             identifier,
             position: Arc::new(Mutex::new(0)),  // Start with initial position of 0
+            write_policy: PositionWritePolicy::default(),
+            save_state: Arc::new(Mutex::new(SaveState {
+                saved_position: 0,
+                unsaved: 0,
+                saved_at: Instant::now(),
+            })),
             clock: Clock {},
         }
     }
 
+    pub fn for_consumer_group(message_store: Arc<dyn MessageStore>, category: String, group: &ConsumerGroup) -> Self {
+        PositionStore::new(message_store, category, Some(group.identifier()))
+    }
+
+    pub fn with_write_policy(self, write_policy: PositionWritePolicy) -> Self {
+        PositionStore { write_policy, ..self }
+    }
+
+    /// Same store reading and writing a different position stream. The cached
+    /// position is not shared with the original.
+    pub fn with_identifier(self, identifier: Option<String>) -> Self {
+        PositionStore::new(self.message_store, self.category, identifier)
+            .with_write_policy(self.write_policy)
+    }
+
+    /// `account:commands:position-1` for the `account:commands` category and identifier `1`.
     pub fn position_stream_name(&self) -> String {
-        if let Some(ref id) = self.identifier {
-            format!("{}:position-{}", self.category, id)
-        } else {
-            format!("{}:position", self.category)
-        }
+        let category = StreamName::category_with_type(&self.category, POSITION_TYPE);
+        StreamName::stream(category.as_str(), self.identifier.as_deref()).into()
     }
 
-    pub async fn get(&self) -> i64 {
-        let message = self.message_store.get_last_message(&self.position_stream_name()).await.unwrap();
+    /// Reads the last recorded position and restores the cached position from it.
+    pub async fn get(&self) -> Result<i64, String> {
+        let message = self.message_store.get_last_message(&self.position_stream_name()).await
+            .map_err(|e| format!("Failed to fetch position: {}", e))?;
         debug!("Getting position for stream {:?}, last message: {:?}", self.position_stream_name(), message);
-        match message {
-            Some(message) => {
-                let event = Recorded::from_message(message).expect("Failed to parse event");
-                event.recorded_position
-            },
+        let recorded_position = match message {
+            Some(message) => TypedMessage::<Recorded>::from_message(message)
+                .map_err(|e| format!("Failed to read position: {}", e))?
+                .recorded_position,
             None => 0,
-        }
+        };
+
+        self.restore(recorded_position).await;
+        Ok(recorded_position)
+    }
+
+    /// Sets the cached position to one already recorded, without writing it again.
+    pub async fn restore(&self, recorded_position: i64) {
+        let mut position = self.position.lock().await;
+        let mut save_state = self.save_state.lock().await;
+        *position = recorded_position;
+        save_state.saved_position = recorded_position;
+        save_state.unsaved = 0;
+        save_state.saved_at = Instant::now();
     }
 
     pub async fn position(&self, account_id: &str) -> Result<i64, String> {
@@ -53,12 +134,62 @@ impl PositionStore {
         Ok(*position)
     }
 
+    /// Records progress up to `new_position`. Positions behind the current one are
+    /// ignored, so concurrent workers finishing out of order never move it back.
     pub async fn update_position(&self, new_position: i64) -> Result<(), String> {
-        info!("Updating position to: {}", new_position);
         let mut position = self.position.lock().await;
+        if new_position < *position {
+            debug!("Ignoring position {} behind current position {}", new_position, *position);
+            return Ok(());
+        }
+        info!("Updating position to: {}", new_position);
         *position = new_position;
 
-        if *position % 5 == 0 {
+        let due = {
+            let mut save_state = self.save_state.lock().await;
+            save_state.unsaved += 1;
+            self.write_policy.due(save_state.unsaved, save_state.saved_at.elapsed())
+        };
+        if due {
+            self.save_position(*position).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the position whenever the interval write policy comes due, so that
+    /// a consumer that goes idle after a message still records it. Never returns;
+    /// run it alongside the subscription.
+    pub async fn flush_when_due(&self) {
+        let interval = match self.write_policy.every_interval {
+            Some(interval) if !interval.is_zero() => interval,
+            _ => return std::future::pending().await,
+        };
+
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let due = {
+                let save_state = self.save_state.lock().await;
+                save_state.unsaved > 0 && self.write_policy.due(save_state.unsaved, save_state.saved_at.elapsed())
+            };
+            if due {
+                if let Err(e) = self.flush().await {
+                    error!("Failed to write position: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Writes the current position if it has moved since it was last saved,
+    /// e.g. before the consumer shuts down.
+    pub async fn flush(&self) -> Result<(), String> {
+        // Held across the save, as in `update_position`, so a newer position
+        // saved concurrently cannot be overwritten with this one
+        let position = self.position.lock().await;
+        let saved_position = self.save_state.lock().await.saved_position;
+        if *position > saved_position {
             self.save_position(*position).await?;
         }
 
@@ -72,16 +203,18 @@ impl PositionStore {
         let event = Recorded {
             recorded_position: position,
             processed_time: Some(now),
-            position: None,
-            message: Message::default(),
-
-         };
-        let message_type = event.event_name();
+            consumer_identifier: self.identifier.clone(),
+        };
+        let message_type = Recorded::MESSAGE_TYPE;
         let data = serde_json::to_value(&event).expect("Failed to serialize event").to_string();
-        self.message_store.write_message(&self.position_stream_name(), message_type, &data, None, None).await;
+        self.message_store.write_message(&self.position_stream_name(), message_type, &data, None, None).await
+            .map_err(|e| format!("Failed to save position: {}", e))?;
+
+        let mut save_state = self.save_state.lock().await;
+        save_state.saved_position = position;
+        save_state.unsaved = 0;
+        save_state.saved_at = Instant::now();
 
         Ok(())
     }
 }
-
-
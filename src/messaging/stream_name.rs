@@ -0,0 +1,176 @@
+use std::fmt;
+
+// Stream names follow Message DB's conventions:
+//
+//   account                    category
+//   account-123                entity stream, id 123
+//   account-123+abc            compound id, cardinal id 123
+//   account:commands           category with the `commands` type
+//   account:position+snapshot  category with the `position` and `snapshot` types
+//   account:commands-123       entity stream in a typed category
+
+const ID_SEPARATOR: char = '-';
+const COMPOUND_ID_SEPARATOR: char = '+';
+const CATEGORY_TYPE_SEPARATOR: char = ':';
+const COMPOUND_TYPE_SEPARATOR: char = '+';
+
+/// Same as Message DB's `category()`: everything before the first `-`.
+pub fn category(stream_name: &str) -> &str {
+    match stream_name.split_once(ID_SEPARATOR) {
+        Some((category, _)) => category,
+        None => stream_name,
+    }
+}
+
+/// Same as Message DB's `id()`: everything after the first `-`, if any.
+pub fn id(stream_name: &str) -> Option<&str> {
+    stream_name.split_once(ID_SEPARATOR).map(|(_, id)| id)
+}
+
+/// Same as Message DB's `cardinal_id()`: the first part of a compound id.
+pub fn cardinal_id(stream_name: &str) -> Option<&str> {
+    id(stream_name).map(|id| match id.split_once(COMPOUND_ID_SEPARATOR) {
+        Some((cardinal_id, _)) => cardinal_id,
+        None => id,
+    })
+}
+
+/// `["123", "abc"]` joins to the compound id `123+abc`.
+pub fn compound_id(ids: &[&str]) -> String {
+    ids.join(&COMPOUND_ID_SEPARATOR.to_string())
+}
+
+/// Same as Message DB's `is_category()`.
+pub fn is_category(stream_name: &str) -> bool {
+    !stream_name.contains(ID_SEPARATOR)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StreamName(String);
+
+impl StreamName {
+    pub fn new(stream_name: &str) -> Self {
+        StreamName(stream_name.to_string())
+    }
+
+    /// `account` and `Some("123")` compose to `account-123`; `None` gives the category itself.
+    pub fn stream(category: &str, id: Option<&str>) -> Self {
+        match id {
+            Some(id) => StreamName(format!("{}{}{}", category, ID_SEPARATOR, id)),
+            None => StreamName(category.to_string()),
+        }
+    }
+
+    pub fn entity(category: &str, id: &str) -> Self {
+        StreamName::stream(category, Some(id))
+    }
+
+    /// `account`, `["123", "abc"]` composes to `account-123+abc`.
+    pub fn compound(category: &str, ids: &[&str]) -> Self {
+        if ids.is_empty() {
+            return StreamName::stream(category, None);
+        }
+        StreamName::stream(category, Some(&compound_id(ids)))
+    }
+
+    /// `account`, `["position", "snapshot"]` composes to `account:position+snapshot`.
+    pub fn category_with_types(entity: &str, types: &[&str]) -> Self {
+        if types.is_empty() {
+            return StreamName(entity.to_string());
+        }
+        let types = types.join(&COMPOUND_TYPE_SEPARATOR.to_string());
+        StreamName(format!("{}{}{}", entity, CATEGORY_TYPE_SEPARATOR, types))
+    }
+
+    pub fn category_with_type(entity: &str, category_type: &str) -> Self {
+        StreamName::category_with_types(entity, &[category_type])
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn category(&self) -> &str {
+        category(&self.0)
+    }
+
+    pub fn id(&self) -> Option<&str> {
+        id(&self.0)
+    }
+
+    /// The parts of a compound id; empty for a category stream.
+    pub fn ids(&self) -> Vec<&str> {
+        match self.id() {
+            Some(id) => id.split(COMPOUND_ID_SEPARATOR).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn cardinal_id(&self) -> Option<&str> {
+        cardinal_id(&self.0)
+    }
+
+    pub fn is_category(&self) -> bool {
+        is_category(&self.0)
+    }
+
+    /// The category without its types, e.g. `account` for `account:commands-123`.
+    pub fn entity_name(&self) -> &str {
+        let category = self.category();
+        match category.split_once(CATEGORY_TYPE_SEPARATOR) {
+            Some((entity, _)) => entity,
+            None => category,
+        }
+    }
+
+    /// The category types, e.g. `["position", "snapshot"]` for `account:position+snapshot`.
+    pub fn types(&self) -> Vec<&str> {
+        match self.category().split_once(CATEGORY_TYPE_SEPARATOR) {
+            Some((_, types)) => types.split(COMPOUND_TYPE_SEPARATOR).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn has_type(&self, category_type: &str) -> bool {
+        self.types().contains(&category_type)
+    }
+
+    /// Adds a type to the category, keeping the id: `account:commands` with
+    /// `dlq` gives `account:commands+dlq`, and `account-123` gives `account:dlq-123`.
+    pub fn add_type(&self, category_type: &str) -> Self {
+        let mut types = self.types();
+        types.push(category_type);
+        let category = StreamName::category_with_types(self.entity_name(), &types);
+        StreamName::stream(category.as_str(), self.id())
+    }
+}
+
+impl fmt::Display for StreamName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for StreamName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for StreamName {
+    fn from(stream_name: &str) -> Self {
+        StreamName::new(stream_name)
+    }
+}
+
+impl From<String> for StreamName {
+    fn from(stream_name: String) -> Self {
+        StreamName(stream_name)
+    }
+}
+
+impl From<StreamName> for String {
+    fn from(stream_name: StreamName) -> Self {
+        stream_name.0
+    }
+}
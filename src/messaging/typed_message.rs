@@ -0,0 +1,129 @@
+use std::fmt;
+use std::ops::Deref;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::db::MessageStoreError;
+use crate::messaging::{HandlerError, Message, Metadata};
+
+/// Data of a command or event: the struct is the message's data serialized
+/// with serde, and `MESSAGE_TYPE` is what is written to the message's type.
+pub trait MessageData: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// Part of the stored format, so it must not change when the type is renamed.
+    const MESSAGE_TYPE: &'static str;
+}
+
+/// Implements `MessageData` along with `Command` or `Event`. The message type is
+/// the type's name; a type renamed after messages were written keeps its old
+/// message type with `= "OldName"`:
+///
+/// ```ignore
+/// message_data!(Event, Opened, Closed, Withdrawn = "Withdrawal");
+/// ```
+#[macro_export]
+macro_rules! message_data {
+    (@message_type $name:ident) => { stringify!($name) };
+    (@message_type $name:ident = $message_type:literal) => { $message_type };
+    ($kind:ident, $($name:ident $(= $message_type:literal)?),+ $(,)?) => {
+        $(
+            impl $crate::messaging::MessageData for $name {
+                const MESSAGE_TYPE: &'static str = $crate::message_data!(@message_type $name $(= $message_type)?);
+            }
+
+            impl $crate::messaging::$kind for $name {}
+        )+
+    };
+}
+
+/// Why a message could not be read as a `TypedMessage<T>`.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The message is of a different type than the one it was decoded as.
+    UnexpectedType { expected: &'static str, actual: String },
+    /// The message's data does not deserialize into the type.
+    Data { message_type: String, source: serde_json::Error },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedType { expected, actual } => {
+                write!(f, "Expected a {} message, got {}", expected, actual)
+            },
+            DecodeError::Data { message_type, source } => {
+                write!(f, "Failed to decode {} data: {}", message_type, source)
+            },
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Data { source, .. } => Some(source),
+            DecodeError::UnexpectedType { .. } => None,
+        }
+    }
+}
+
+impl From<DecodeError> for HandlerError {
+    fn from(e: DecodeError) -> Self {
+        // The same data will never decode differently
+        HandlerError::Permanent(e.to_string())
+    }
+}
+
+impl From<DecodeError> for MessageStoreError {
+    fn from(e: DecodeError) -> Self {
+        MessageStoreError::Serialization(e.to_string())
+    }
+}
+
+/// A message whose data has been decoded into `T`, together with the envelope
+/// it was read in. Dereferences to the data.
+#[derive(Debug, Clone)]
+pub struct TypedMessage<T> {
+    pub data: T,
+    pub message: Message,
+}
+
+impl<T: MessageData> TypedMessage<T> {
+    /// Decodes the message's data as `T`. The message type must be `T::MESSAGE_TYPE`.
+    pub fn from_message(message: Message) -> Result<Self, DecodeError> {
+        let expected = T::MESSAGE_TYPE;
+        if message.message_type != expected {
+            return Err(DecodeError::UnexpectedType { expected, actual: message.message_type });
+        }
+
+        let data = serde_json::from_str(&message.data)
+            .map_err(|source| DecodeError::Data { message_type: message.message_type.clone(), source })?;
+        Ok(TypedMessage { data, message })
+    }
+}
+
+impl<T> TypedMessage<T> {
+    pub fn position(&self) -> Option<i64> {
+        self.message.position
+    }
+
+    pub fn global_position(&self) -> Option<i64> {
+        self.message.global_position
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.message.metadata
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+}
+
+impl<T> Deref for TypedMessage<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
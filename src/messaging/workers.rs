@@ -0,0 +1,144 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeSet;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::db::SubscriptionError;
+use crate::messaging::{stream_name, Message, PositionStore};
+
+/// Messages each worker may have queued before dispatch waits for it to catch up.
+const WORKER_QUEUE_SIZE: usize = 16;
+
+/// Global positions that have been dispatched but not yet completed. Messages
+/// finish out of order across streams, so the position that is safe to record
+/// is the one just before the oldest message still in flight.
+#[derive(Default)]
+struct InFlight {
+    positions: BTreeSet<i64>,
+    last_dispatched: i64,
+    last_completed: i64,
+}
+
+impl InFlight {
+    fn dispatched(&mut self, global_position: i64) {
+        self.positions.insert(global_position);
+        self.last_dispatched = self.last_dispatched.max(global_position);
+    }
+
+    /// Marks the message done and returns the new safe position, if it moved.
+    fn completed(&mut self, global_position: i64) -> Option<i64> {
+        self.positions.remove(&global_position);
+        let completed = match self.positions.first() {
+            Some(oldest) => oldest - 1,
+            None => self.last_dispatched,
+        };
+        if completed > self.last_completed {
+            self.last_completed = completed;
+            Some(completed)
+        } else {
+            None
+        }
+    }
+}
+
+/// Processes messages on a fixed number of tasks. Every message of an entity goes
+/// to the same worker, keyed by the stream's cardinal id (`123` for both
+/// `account:commands-123` and `account:commands-123+retry`), so an entity's
+/// messages are handled one at a time in order while different entities
+/// proceed in parallel. Streams without an id share a worker.
+pub(crate) struct WorkerPool {
+    senders: Vec<mpsc::Sender<Message>>,
+    workers: Vec<JoinHandle<()>>,
+    in_flight: Arc<Mutex<InFlight>>,
+    failure: Arc<Mutex<Option<SubscriptionError>>>,
+    stop: CancellationToken,
+}
+
+impl WorkerPool {
+    /// Starts `size` workers that call `process` for each message. `process`
+    /// returning `Err` stops the pool: `stop` is cancelled and the message's
+    /// position is never recorded. Workers also stop when `stop` is cancelled,
+    /// leaving any queued messages to be read again on the next start.
+    pub(crate) fn start<F, Fut>(size: usize, position_store: PositionStore, stop: CancellationToken, process: F) -> Self
+    where
+        F: Fn(Message) -> Fut + Clone + Send + Sync + 'static,
+        Fut: std::future::Future<Output = Result<(), SubscriptionError>> + Send,
+    {
+        let in_flight = Arc::new(Mutex::new(InFlight::default()));
+        let failure = Arc::new(Mutex::new(None));
+        let mut senders = Vec::with_capacity(size);
+        let mut workers = Vec::with_capacity(size);
+
+        for worker in 0..size.max(1) {
+            let (sender, mut receiver) = mpsc::channel::<Message>(WORKER_QUEUE_SIZE);
+            let process = process.clone();
+            let position_store = position_store.clone();
+            let worker_stop = stop.clone();
+            let in_flight = in_flight.clone();
+            let failure = failure.clone();
+            workers.push(tokio::spawn(async move {
+                while let Some(message) = receiver.recv().await {
+                    if worker_stop.is_cancelled() {
+                        break;
+                    }
+                    let global_position = message.global_position.unwrap_or_default();
+                    debug!("Worker {} processing message at position {}", worker, global_position);
+                    if let Err(e) = process(message).await {
+                        failure.lock().unwrap().get_or_insert(e);
+                        worker_stop.cancel();
+                        break;
+                    }
+
+                    let completed = in_flight.lock().unwrap().completed(global_position);
+                    if let Some(position) = completed {
+                        if let Err(e) = position_store.update_position(position).await {
+                            error!("Failed to update position: {}", e);
+                        }
+                    }
+                }
+            }));
+            senders.push(sender);
+        }
+
+        WorkerPool { senders, workers, in_flight, failure, stop }
+    }
+
+    /// Queues the message on its entity's worker, waiting while that worker's queue is full.
+    pub(crate) async fn dispatch(&self, message: Message) {
+        let worker = self.worker_for(&message.stream_name);
+        if let Some(global_position) = message.global_position {
+            self.in_flight.lock().unwrap().dispatched(global_position);
+        }
+        // Only fails once the worker has stopped. The message stays in flight so its
+        // position is not recorded, and a worker that stopped without being asked
+        // to (it panicked) interrupts the pool so the consumer is restarted.
+        if self.senders[worker].send(message).await.is_err() && !self.stop.is_cancelled() {
+            self.failure.lock().unwrap().get_or_insert(SubscriptionError::Interrupted(format!("Worker {} stopped", worker)));
+            self.stop.cancel();
+        }
+    }
+
+    fn worker_for(&self, stream_name: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        stream_name::cardinal_id(stream_name).unwrap_or(stream_name).hash(&mut hasher);
+        (hasher.finish() % self.senders.len() as u64) as usize
+    }
+
+    /// Stops accepting messages and waits for the workers to finish. Returns why
+    /// the pool stopped early, if it did.
+    pub(crate) async fn shutdown(self) -> Option<SubscriptionError> {
+        drop(self.senders);
+        for (worker, task) in self.workers.into_iter().enumerate() {
+            if let Err(e) = task.await {
+                error!("Worker task failed: {}", e);
+                self.failure.lock().unwrap().get_or_insert(SubscriptionError::Interrupted(format!("Worker {} failed: {}", worker, e)));
+            }
+        }
+        self.failure.lock().unwrap().take()
+    }
+}
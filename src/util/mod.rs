@@ -1,3 +1,6 @@
 pub mod clock;
+pub mod retry;
+pub mod shutdown;
 
-pub use clock::Clock;
\ No newline at end of file
+pub use clock::Clock;
+pub use retry::RetryPolicy;
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff with jitter, giving up after `max_failures` consecutive
+/// failures (or never, when `None`).
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Fraction of the backoff to randomly add or remove, from 0.0 to 1.0.
+    pub jitter: f64,
+    pub max_failures: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_failures: Some(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn never() -> Self {
+        RetryPolicy { max_failures: Some(0), ..RetryPolicy::default() }
+    }
+
+    /// Whether to give up after this many consecutive failures.
+    pub fn exhausted(&self, failures: u32) -> bool {
+        matches!(self.max_failures, Some(max) if failures > max)
+    }
+
+    /// How long to wait before retrying after the given number of consecutive failures.
+    pub fn backoff(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            1.0
+        };
+
+        Duration::from_secs_f64(backoff * factor)
+    }
+}
@@ -0,0 +1,27 @@
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Cancels the token on the first SIGINT (Ctrl-C) or SIGTERM.
+pub fn cancel_on_signal(shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("Shutdown signal received, stopping consumers");
+        shutdown.cancel();
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c().await.expect("Failed to listen for Ctrl-C");
+}